@@ -16,4 +16,6 @@ fn main() {
 
     // pass the disk image paths as env variables to the `main.rs`
     println!("cargo:rustc-env=UEFI_PATH={}", uefi_path.display());
+    // and the kernel ELF, whose debug info symbolizes crash reports
+    println!("cargo:rustc-env=KERNEL_PATH={}", kernel.display());
 }
\ No newline at end of file
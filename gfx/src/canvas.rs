@@ -0,0 +1,579 @@
+// An off-screen pixel buffer in the framebuffer's own layout, with the drawing primitives the
+// kernel's back buffer is built on. Every drawing call records the rectangle it touched, so
+// `clear` only blanks what was drawn since the previous clear and the owner only has to copy
+// what changed to the screen.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::damage::{DamageList, Rect};
+use crate::pixel::{Color, Encoded, PixelEncoder, MAX_BYTES_PER_PIXEL};
+use crate::raster::{self, CircleOctant, Line};
+
+pub struct Canvas {
+    pixels: Vec<u8>,
+    width: usize,
+    height: usize,
+    /// Pixels from the start of one row to the start of the next.
+    stride: usize,
+    bytes_per_pixel: usize,
+    encoder: PixelEncoder,
+    /// Changed since the last `take_dirty`.
+    dirty: DamageList,
+    /// Possibly not black since the last `clear`.
+    drawn: DamageList,
+}
+
+/// An RGBA image in memory: four bytes per pixel, rows stored one after another.
+pub struct Image<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: &'a [u8],
+}
+
+/// A colour ready to be written, see [Canvas::paint].
+enum Paint {
+    Solid(Encoded),
+    Blend(Color),
+}
+
+impl Canvas {
+    /// A black canvas laid out like a framebuffer of `stride` pixels per row. All of it counts as
+    /// changed, so the first copy to the screen wipes whatever was there before.
+    pub fn new(width: usize, height: usize, stride: usize, bytes_per_pixel: usize, encoder: PixelEncoder) -> Self {
+        assert!(stride >= width, "a row of {width} pixels does not fit a stride of {stride}");
+        assert!(
+            (1..=MAX_BYTES_PER_PIXEL).contains(&bytes_per_pixel),
+            "unsupported pixel size of {bytes_per_pixel} bytes"
+        );
+        let mut canvas = Self {
+            pixels: vec![0; stride * height * bytes_per_pixel],
+            width,
+            height,
+            stride,
+            bytes_per_pixel,
+            encoder,
+            dirty: DamageList::new(),
+            drawn: DamageList::new(),
+        };
+        canvas.dirty.add(canvas.bounds());
+        canvas
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// The part of the box at `(x, y)` with the given size that lies on the canvas.
+    pub fn clip_signed(&self, x: isize, y: isize, width: isize, height: isize) -> Rect {
+        raster::clip(x, y, width, height, self.width, self.height)
+    }
+
+    /// Pixel layout of the buffer, for code that prepares pixels for [Self::copy_pixels].
+    pub fn encoder(&self) -> PixelEncoder {
+        self.encoder
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bytes_per_pixel
+    }
+
+    /// The whole buffer, `stride` pixels per row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Byte range of row `y` of `rect` in the buffer, and in a framebuffer with the same layout.
+    pub fn row_bytes(&self, rect: &Rect, y: usize) -> core::ops::Range<usize> {
+        let start = (y * self.stride + rect.x) * self.bytes_per_pixel;
+        start..start + rect.width * self.bytes_per_pixel
+    }
+
+    /// Blanks everything drawn since the last call.
+    pub fn clear(&mut self) {
+        let drawn = core::mem::take(&mut self.drawn);
+        for rect in drawn.iter() {
+            for y in rect.y..rect.bottom() {
+                let row = self.row_bytes(rect, y);
+                self.pixels[row].fill(0);
+            }
+            self.dirty.add(*rect);
+        }
+    }
+
+    /// Everything that changed since the last call.
+    pub fn take_dirty(&mut self) -> DamageList {
+        core::mem::take(&mut self.dirty)
+    }
+
+    /// Records that `rect` is about to be drawn over. The primitives do this themselves; a
+    /// caller drawing something in many small pieces, like a line of text, can record the whole
+    /// box first so the pieces find it already covered.
+    pub fn damage(&mut self, rect: Rect) {
+        let rect = rect.clip(&self.bounds());
+        self.dirty.add(rect);
+        self.drawn.add(rect);
+    }
+
+    /// Copies pixels that are already encoded for this canvas into `rect`, clipped to the canvas.
+    /// `pixels` holds `rect.width` pixels per row with no padding.
+    pub fn copy_pixels(&mut self, rect: Rect, pixels: &[u8]) {
+        let clipped = rect.clip(&self.bounds());
+        self.damage(clipped);
+        for y in clipped.y..clipped.bottom() {
+            let row = self.row_bytes(&clipped, y);
+            let start = ((y - rect.y) * rect.width + clipped.x - rect.x) * self.bytes_per_pixel;
+            self.pixels[row.clone()].copy_from_slice(&pixels[start..start + row.len()]);
+        }
+    }
+}
+
+/// Drawing primitives. Each one clips its shape to the canvas once, records the damage for its
+/// bounding box once, and then writes whole rows where it can, instead of going through the
+/// bounds checks of `draw_pixel` for every pixel. The shape arithmetic is in [raster].
+impl Canvas {
+    pub fn draw_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        self.damage(Rect::new(x, y, 1, 1));
+        let paint = self.paint(color);
+        self.put(x as isize, y as isize, &paint);
+    }
+
+    /// Fills a rectangle with one colour, clipped to the canvas.
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let rect = rect.clip(&self.bounds());
+        let paint = self.paint(color);
+        if rect.is_empty() {
+            return;
+        }
+        self.damage(rect);
+        for y in rect.y..rect.bottom() {
+            self.span(rect.x as isize, rect.right() as isize, y as isize, &paint);
+        }
+    }
+
+    /// Draws the one pixel wide outline of a rectangle.
+    pub fn stroke_rect(&mut self, rect: Rect, color: Color) {
+        if rect.is_empty() {
+            return;
+        }
+        let Rect { x, y, width, height } = rect;
+        self.fill_rect(Rect::new(x, y, width, 1), color);
+        self.fill_rect(Rect::new(x, rect.bottom() - 1, width, 1), color);
+        self.fill_rect(Rect::new(x, y, 1, height), color);
+        self.fill_rect(Rect::new(rect.right() - 1, y, 1, height), color);
+    }
+
+    /// Draws a one pixel wide line between two points, both included, with Bresenham's algorithm.
+    pub fn line(&mut self, from: (isize, isize), to: (isize, isize), color: Color) {
+        let paint = self.paint(color);
+        let (left, right) = (from.0.min(to.0), from.0.max(to.0));
+        let (top, bottom) = (from.1.min(to.1), from.1.max(to.1));
+        let clipped = self.clip_signed(left, top, right - left + 1, bottom - top + 1);
+        if clipped.is_empty() {
+            return;
+        }
+        self.damage(clipped);
+
+        if from.1 == to.1 {
+            self.span(left, right + 1, from.1, &paint);
+            return;
+        }
+        for (x, y) in Line::new(from, to) {
+            self.put(x, y, &paint);
+        }
+    }
+
+    /// Fills a circle around `centre`; a radius of zero is a single pixel.
+    pub fn fill_circle(&mut self, centre: (isize, isize), radius: usize, color: Color) {
+        let size = 2 * radius + 1;
+        let rect = Rect::new(0, 0, size, size);
+        self.fill_rounded_at(centre.0 - radius as isize, centre.1 - radius as isize, rect, radius, color);
+    }
+
+    /// Draws the one pixel wide outline of a circle around `centre`.
+    pub fn stroke_circle(&mut self, centre: (isize, isize), radius: usize, color: Color) {
+        let paint = self.paint(color);
+        let radius = radius as isize;
+        let clipped = self.clip_signed(centre.0 - radius, centre.1 - radius, 2 * radius + 1, 2 * radius + 1);
+        if clipped.is_empty() {
+            return;
+        }
+        self.damage(clipped);
+        self.stroke_corners(centre, centre, radius, &paint);
+    }
+
+    /// Fills a rectangle whose corners are quarter circles of `radius`, shrunk to fit if needed.
+    pub fn fill_rounded_rect(&mut self, rect: Rect, radius: usize, color: Color) {
+        self.fill_rounded_at(rect.x as isize, rect.y as isize, rect, radius, color);
+    }
+
+    /// Draws the one pixel wide outline of a rounded rectangle.
+    pub fn stroke_rounded_rect(&mut self, rect: Rect, radius: usize, color: Color) {
+        let paint = self.paint(color);
+        let clipped = rect.clip(&self.bounds());
+        if rect.is_empty() || clipped.is_empty() {
+            return;
+        }
+        self.damage(clipped);
+
+        let radius = radius.min((rect.width - 1) / 2).min((rect.height - 1) / 2) as isize;
+        let (left, top) = (rect.x as isize, rect.y as isize);
+        let (right, bottom) = (rect.right() as isize - 1, rect.bottom() as isize - 1);
+        self.span(left + radius, right - radius + 1, top, &paint);
+        self.span(left + radius, right - radius + 1, bottom, &paint);
+        for y in top + radius..=bottom - radius {
+            self.put(left, y, &paint);
+            self.put(right, y, &paint);
+        }
+        self.stroke_corners((left + radius, top + radius), (right - radius, bottom - radius), radius, &paint);
+    }
+
+    /// Copies `image` with its top left corner at `at`, clipping whatever falls off the canvas.
+    /// Pixels are blended by their own alpha.
+    pub fn blit(&mut self, image: &Image, at: (isize, isize)) {
+        let clipped = self.clip_signed(at.0, at.1, image.width as isize, image.height as isize);
+        if clipped.is_empty() || image.pixels.len() < image.width * image.height * 4 {
+            return;
+        }
+        self.damage(clipped);
+
+        let bytes_per_pixel = self.bytes_per_pixel;
+        let encoder = self.encoder;
+        let first_column = (clipped.x as isize - at.0) as usize;
+        for y in clipped.y..clipped.bottom() {
+            let source_row = (y as isize - at.1) as usize;
+            let start = (source_row * image.width + first_column) * 4;
+            let source = &image.pixels[start..start + clipped.width * 4];
+            let row = self.row_bytes(&clipped, y);
+            for (pixel, &[r, g, b, a]) in self.pixels[row].chunks_exact_mut(bytes_per_pixel).zip(source.as_chunks::<4>().0) {
+                let color = Color::rgba(r, g, b, a);
+                let [r, g, b] = match color.a {
+                    0 => continue,
+                    255 => [color.r, color.g, color.b],
+                    _ => color.over(encoder.decode(pixel)),
+                };
+                pixel.copy_from_slice(&encoder.encode(r, g, b)[..bytes_per_pixel]);
+            }
+        }
+    }
+
+    /// Paints a coverage mask, such as a glyph, with its top left corner at `at`: each byte of
+    /// `mask` becomes a `scale` by `scale` cell of `color`, faded by the byte's coverage so edges
+    /// blend into what is below.
+    pub fn draw_mask(&mut self, mask: &[&[u8]], at: (isize, isize), scale: usize, color: Color) {
+        let columns = mask.iter().map(|row| row.len()).max().unwrap_or(0);
+        let scale_signed = scale as isize;
+        let clipped = self.clip_signed(at.0, at.1, (columns * scale) as isize, (mask.len() * scale) as isize);
+        if clipped.is_empty() {
+            return;
+        }
+        self.damage(clipped);
+
+        for (row_index, row) in mask.iter().enumerate() {
+            let y = at.1 + row_index as isize * scale_signed;
+            for (column, &coverage) in row.iter().enumerate() {
+                if coverage == 0 {
+                    continue;
+                }
+                let cell = self.clip_signed(at.0 + column as isize * scale_signed, y, scale_signed, scale_signed);
+                let paint = self.paint(color.faded(coverage));
+                for cell_y in cell.y..cell.bottom() {
+                    let bytes = self.row_bytes(&cell, cell_y);
+                    self.fill_pixels(bytes, &paint);
+                }
+            }
+        }
+    }
+
+    /// Shared by filled circles and rounded rectangles: fills `rect`'s size at `(x, y)`, which
+    /// may be partly off the canvas, one row at a time, insetting the rows within `radius` of the
+    /// top and bottom edges to round the corners.
+    fn fill_rounded_at(&mut self, x: isize, y: isize, rect: Rect, radius: usize, color: Color) {
+        let paint = self.paint(color);
+        let (width, height) = (rect.width as isize, rect.height as isize);
+        let clipped = self.clip_signed(x, y, width, height);
+        if clipped.is_empty() {
+            return;
+        }
+        self.damage(clipped);
+
+        for row in 0..rect.height {
+            let (start, end) = raster::rounded_row(row, rect.width, rect.height, radius);
+            self.span(x + start as isize, x + end as isize, y + row as isize, &paint);
+        }
+    }
+
+    /// Plots the midpoint-circle arcs of radius `radius` in four corners: the left half around
+    /// `top_left.0`, the right half around `bottom_right.0`, and likewise for top and bottom.
+    /// With both centres equal this draws a whole circle.
+    fn stroke_corners(&mut self, top_left: (isize, isize), bottom_right: (isize, isize), radius: isize, paint: &Paint) {
+        for (x, y) in CircleOctant::new(radius as usize) {
+            for (dx, dy) in [(x, y), (y, x)] {
+                self.put(bottom_right.0 + dx, bottom_right.1 + dy, paint);
+                self.put(top_left.0 - dx, bottom_right.1 + dy, paint);
+                self.put(bottom_right.0 + dx, top_left.1 - dy, paint);
+                self.put(top_left.0 - dx, top_left.1 - dy, paint);
+            }
+        }
+    }
+
+    /// How to put `color` into the buffer: opaque colours are encoded once and copied,
+    /// translucent ones have to be mixed with every pixel they cover.
+    fn paint(&self, color: Color) -> Paint {
+        if color.is_opaque() {
+            Paint::Solid(self.encoder.encode(color.r, color.g, color.b))
+        } else {
+            Paint::Blend(color)
+        }
+    }
+
+    /// Writes one pixel if it is on the canvas, without recording damage.
+    fn put(&mut self, x: isize, y: isize, paint: &Paint) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let start = (y as usize * self.stride + x as usize) * self.bytes_per_pixel;
+        self.fill_pixels(start..start + self.bytes_per_pixel, paint);
+    }
+
+    /// Fills the pixels `x_start..x_end` of row `y` that are on the canvas, without recording damage.
+    fn span(&mut self, x_start: isize, x_end: isize, y: isize, paint: &Paint) {
+        let row = self.clip_signed(x_start, y, x_end - x_start, 1);
+        if row.is_empty() {
+            return;
+        }
+        let bytes = self.row_bytes(&row, row.y);
+        self.fill_pixels(bytes, paint);
+    }
+
+    /// Paints the pixels in the byte range `bytes` of the buffer.
+    fn fill_pixels(&mut self, bytes: core::ops::Range<usize>, paint: &Paint) {
+        let bytes_per_pixel = self.bytes_per_pixel;
+        let encoder = self.encoder;
+        let pixels = self.pixels[bytes].chunks_exact_mut(bytes_per_pixel);
+        match paint {
+            Paint::Solid(encoded) => {
+                for pixel in pixels {
+                    pixel.copy_from_slice(&encoded[..bytes_per_pixel]);
+                }
+            }
+            Paint::Blend(color) if color.a == 0 => {}
+            Paint::Blend(color) => {
+                for pixel in pixels {
+                    let [r, g, b] = color.over(encoder.decode(pixel));
+                    pixel.copy_from_slice(&encoder.encode(r, g, b)[..bytes_per_pixel]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color::rgb(255, 0, 0);
+
+    /// A 20 by 10 canvas in 32-bit BGR, with two pixels of padding at the end of each row.
+    fn canvas() -> Canvas {
+        let mut canvas = Canvas::new(20, 10, 22, 4, PixelEncoder::channels([16, 8, 0], 4));
+        canvas.take_dirty();
+        canvas
+    }
+
+    fn pixel(canvas: &Canvas, x: usize, y: usize) -> [u8; 3] {
+        let start = canvas.row_bytes(&Rect::new(x, y, 1, 1), y).start;
+        canvas.encoder().decode(&canvas.pixels()[start..start + 4])
+    }
+
+    /// The canvas as text, `#` for red pixels and `.` for black ones.
+    fn picture(canvas: &Canvas, rect: Rect) -> Vec<String> {
+        (rect.y..rect.bottom())
+            .map(|y| {
+                (rect.x..rect.right())
+                    .map(|x| match pixel(canvas, x, y) {
+                        [0, 0, 0] => '.',
+                        [255, 0, 0] => '#',
+                        _ => '?',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn lit(canvas: &Canvas) -> usize {
+        picture(canvas, canvas.bounds()).iter().map(|row| row.matches('#').count()).sum()
+    }
+
+    fn dirty(canvas: &mut Canvas) -> Vec<Rect> {
+        canvas.take_dirty().iter().copied().collect()
+    }
+
+    #[test]
+    fn a_new_canvas_is_black_and_dirty_everywhere() {
+        let mut canvas = Canvas::new(20, 10, 22, 4, PixelEncoder::channels([16, 8, 0], 4));
+        assert_eq!(lit(&canvas), 0);
+        assert_eq!(dirty(&mut canvas), [Rect::new(0, 0, 20, 10)]);
+        assert_eq!(dirty(&mut canvas), []);
+    }
+
+    #[test]
+    fn rects_are_clipped_and_leave_the_row_padding_alone() {
+        let mut canvas = canvas();
+        canvas.fill_rect(Rect::new(15, 8, 10, 10), RED);
+        assert_eq!(lit(&canvas), 10);
+        assert_eq!(dirty(&mut canvas), [Rect::new(15, 8, 5, 2)]);
+        let padding = canvas.row_bytes(&Rect::new(20, 8, 2, 1), 8);
+        assert!(canvas.pixels()[padding].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn clearing_blanks_only_what_was_drawn() {
+        let mut canvas = canvas();
+        canvas.fill_rect(Rect::new(2, 2, 3, 3), RED);
+        canvas.take_dirty();
+        canvas.clear();
+        assert_eq!(lit(&canvas), 0);
+        assert_eq!(dirty(&mut canvas), [Rect::new(2, 2, 3, 3)]);
+
+        canvas.clear();
+        assert_eq!(dirty(&mut canvas), []);
+    }
+
+    #[test]
+    fn pixels_off_the_canvas_are_ignored() {
+        let mut canvas = canvas();
+        canvas.draw_pixel(20, 0, RED);
+        canvas.draw_pixel(0, 10, RED);
+        assert_eq!(dirty(&mut canvas), []);
+        canvas.draw_pixel(19, 9, RED);
+        assert_eq!(pixel(&canvas, 19, 9), [255, 0, 0]);
+        assert_eq!(dirty(&mut canvas), [Rect::new(19, 9, 1, 1)]);
+    }
+
+    #[test]
+    fn translucent_colours_blend_with_what_is_below() {
+        let mut canvas = canvas();
+        canvas.fill_rect(Rect::new(0, 0, 4, 1), Color::rgb(0, 0, 200));
+        canvas.fill_rect(Rect::new(2, 0, 4, 1), Color::rgba(255, 255, 255, 128));
+        assert_eq!(pixel(&canvas, 1, 0), [0, 0, 200]);
+        assert_eq!(pixel(&canvas, 2, 0), [128, 128, 228]);
+        assert_eq!(pixel(&canvas, 4, 0), [128, 128, 128]);
+
+        canvas.fill_rect(Rect::new(0, 0, 6, 1), Color::rgba(255, 0, 0, 0));
+        assert_eq!(pixel(&canvas, 1, 0), [0, 0, 200]);
+    }
+
+    #[test]
+    fn stroked_rects_are_hollow() {
+        let mut canvas = canvas();
+        canvas.stroke_rect(Rect::new(1, 1, 5, 4), RED);
+        assert_eq!(picture(&canvas, Rect::new(0, 0, 7, 6)), [".......", ".#####.", ".#...#.", ".#...#.", ".#####.", "......."]);
+        assert_eq!(dirty(&mut canvas), [Rect::new(1, 1, 5, 4)]);
+    }
+
+    #[test]
+    fn lines_are_drawn_end_to_end_and_clipped() {
+        let mut canvas = canvas();
+        canvas.line((0, 0), (3, 3), RED);
+        canvas.line((5, 1), (8, 1), RED);
+        assert_eq!(picture(&canvas, Rect::new(0, 0, 9, 4)), ["#........", ".#...####", "..#......", "...#....."]);
+
+        let mut canvas = self::canvas();
+        canvas.line((-5, 5), (25, 5), RED);
+        assert_eq!(lit(&canvas), 20);
+        assert_eq!(dirty(&mut canvas), [Rect::new(0, 5, 20, 1)]);
+        canvas.line((-5, -5), (-1, 20), RED);
+        assert_eq!(dirty(&mut canvas), []);
+    }
+
+    #[test]
+    fn circles_are_round_and_clipped() {
+        let mut canvas = canvas();
+        canvas.fill_circle((3, 3), 2, RED);
+        assert_eq!(picture(&canvas, Rect::new(1, 1, 5, 5)), ["..#..", ".###.", "#####", ".###.", "..#.."]);
+        assert_eq!(dirty(&mut canvas), [Rect::new(1, 1, 5, 5)]);
+
+        let mut canvas = self::canvas();
+        canvas.stroke_circle((3, 3), 2, RED);
+        assert_eq!(picture(&canvas, Rect::new(1, 1, 5, 5)), [".###.", "#...#", "#...#", "#...#", ".###."]);
+
+        let mut canvas = self::canvas();
+        canvas.fill_circle((0, 0), 2, RED);
+        assert_eq!(picture(&canvas, Rect::new(0, 0, 3, 3)), ["###", "##.", "#.."]);
+        assert_eq!(dirty(&mut canvas), [Rect::new(0, 0, 3, 3)]);
+    }
+
+    #[test]
+    fn rounded_rects_cut_their_corners() {
+        let mut canvas = canvas();
+        canvas.fill_rounded_rect(Rect::new(0, 0, 8, 5), 2, RED);
+        assert_eq!(picture(&canvas, Rect::new(0, 0, 8, 5)), ["..####..", ".######.", "########", ".######.", "..####.."]);
+
+        let mut canvas = self::canvas();
+        canvas.stroke_rounded_rect(Rect::new(0, 0, 8, 5), 1, RED);
+        assert_eq!(picture(&canvas, Rect::new(0, 0, 8, 5)), [".######.", "#......#", "#......#", "#......#", ".######."]);
+        assert_eq!(dirty(&mut canvas), [Rect::new(0, 0, 8, 5)]);
+    }
+
+    #[test]
+    fn images_are_blended_by_their_own_alpha_and_clipped() {
+        let mut canvas = canvas();
+        canvas.fill_rect(Rect::new(0, 0, 3, 1), Color::rgb(0, 0, 200));
+        canvas.take_dirty();
+        #[rustfmt::skip]
+        let pixels = [
+            255, 0, 0, 255,   255, 0, 0, 0,   255, 255, 255, 128,
+        ];
+        let image = Image { width: 3, height: 1, pixels: &pixels };
+        canvas.blit(&image, (0, 0));
+        assert_eq!([pixel(&canvas, 0, 0), pixel(&canvas, 1, 0), pixel(&canvas, 2, 0)], [[255, 0, 0], [0, 0, 200], [128, 128, 228]]);
+        assert_eq!(dirty(&mut canvas), [Rect::new(0, 0, 3, 1)]);
+
+        canvas.blit(&image, (-2, 9));
+        assert_eq!(dirty(&mut canvas), [Rect::new(0, 9, 1, 1)]);
+        assert_eq!(pixel(&canvas, 0, 9), [128, 128, 128]);
+    }
+
+    #[test]
+    fn images_too_short_for_their_size_are_skipped() {
+        let mut canvas = canvas();
+        canvas.blit(&Image { width: 2, height: 2, pixels: &[255; 12] }, (0, 0));
+        assert_eq!(dirty(&mut canvas), []);
+        assert_eq!(lit(&canvas), 0);
+    }
+
+    #[test]
+    fn masks_are_scaled_and_faded_by_coverage() {
+        let mut canvas = canvas();
+        canvas.draw_mask(&[&[255, 0], &[0, 128]], (1, 1), 2, RED);
+        assert_eq!(picture(&canvas, Rect::new(1, 1, 4, 2)), ["##..", "##.."]);
+        assert_eq!(pixel(&canvas, 3, 3), [128, 0, 0]);
+        assert_eq!(pixel(&canvas, 4, 4), [128, 0, 0]);
+        assert_eq!(dirty(&mut canvas), [Rect::new(1, 1, 4, 4)]);
+
+        canvas.draw_mask(&[&[255, 255]], (19, -1), 2, RED);
+        assert_eq!(dirty(&mut canvas), [Rect::new(19, 0, 1, 1)]);
+        assert_eq!(pixel(&canvas, 19, 0), [255, 0, 0]);
+    }
+
+    #[test]
+    fn encoded_pixels_are_copied_where_they_fit() {
+        let mut canvas = canvas();
+        let red = canvas.encoder().encode(255, 0, 0);
+        let pixels: Vec<u8> = (0..6).flat_map(|_| red[..4].to_vec()).collect();
+        canvas.copy_pixels(Rect::new(18, 9, 3, 2), &pixels);
+        assert_eq!(lit(&canvas), 2);
+        assert_eq!(dirty(&mut canvas), [Rect::new(18, 9, 2, 1)]);
+    }
+}
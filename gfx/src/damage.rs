@@ -0,0 +1,262 @@
+// Bookkeeping for the parts of the screen that changed, so that only those need to be cleared
+// and copied to the framebuffer.
+
+/// Rectangle of pixels covering `x..x + width` and `y..y + height`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn area(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x && other.y >= self.y && other.right() <= self.right() && other.bottom() <= self.bottom()
+    }
+
+    /// True if the rectangles overlap or share an edge, so their union wastes no pixels on the seam.
+    pub fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right() && other.x <= self.right() && self.y <= other.bottom() && other.y <= self.bottom()
+    }
+
+    /// Smallest rectangle covering both.
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+
+    /// The part of `self` inside `bounds`, empty if they do not overlap.
+    pub fn clip(&self, bounds: &Rect) -> Rect {
+        let x = self.x.max(bounds.x);
+        let y = self.y.max(bounds.y);
+        let right = self.right().min(bounds.right());
+        let bottom = self.bottom().min(bounds.bottom());
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+}
+
+/// Most rectangles kept apart before the closest ones are merged.
+const MAX_RECTS: usize = 16;
+
+/// A set of pixels kept as a few non-overlapping rectangles. Adding a rectangle merges it with
+/// any it touches, so every pixel is covered at most once and nothing is copied twice.
+pub struct DamageList {
+    rects: [Rect; MAX_RECTS],
+    len: usize,
+}
+
+impl DamageList {
+    pub const fn new() -> Self {
+        Self { rects: [Rect::new(0, 0, 0, 0); MAX_RECTS], len: 0 }
+    }
+
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() || self.iter().any(|r| r.contains(&rect)) {
+            return;
+        }
+
+        let mut rect = rect;
+        loop {
+            let touching = self.iter().position(|r| r.touches(&rect));
+            if let Some(i) = touching {
+                rect = rect.union(&self.remove(i));
+            } else if self.len == MAX_RECTS {
+                // Out of room: grow whichever rectangle the new one costs the fewest extra pixels.
+                let i = (0..self.len)
+                    .min_by_key(|&i| self.rects[i].union(&rect).area() - self.rects[i].area())
+                    .unwrap();
+                rect = rect.union(&self.remove(i));
+            } else {
+                break;
+            }
+        }
+        self.rects[self.len] = rect;
+        self.len += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rect> {
+        self.rects[..self.len].iter()
+    }
+
+    /// Total number of pixels covered.
+    pub fn area(&self) -> usize {
+        self.iter().map(Rect::area).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn remove(&mut self, index: usize) -> Rect {
+        let rect = self.rects[index];
+        self.len -= 1;
+        self.rects[index] = self.rects[self.len];
+        rect
+    }
+}
+
+impl Default for DamageList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rects(list: &DamageList) -> Vec<Rect> {
+        list.iter().copied().collect()
+    }
+
+    /// Pixels of a `size` by `size` grid covered by each of `rects`, counted per pixel.
+    fn coverage(rects: &[Rect], size: usize) -> Vec<usize> {
+        let mut counts = vec![0; size * size];
+        for rect in rects {
+            for y in rect.y..rect.bottom() {
+                for x in rect.x..rect.right() {
+                    counts[y * size + x] += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn empty_rects_are_ignored() {
+        let mut list = DamageList::new();
+        list.add(Rect::new(5, 5, 0, 10));
+        list.add(Rect::new(5, 5, 10, 0));
+        assert_eq!(list.iter().count(), 0);
+        assert_eq!(list.area(), 0);
+    }
+
+    #[test]
+    fn rects_sharing_an_edge_merge() {
+        let mut list = DamageList::new();
+        list.add(Rect::new(0, 0, 10, 10));
+        list.add(Rect::new(10, 0, 10, 10));
+        assert_eq!(rects(&list), [Rect::new(0, 0, 20, 10)]);
+
+        list.add(Rect::new(0, 10, 5, 5));
+        assert_eq!(rects(&list), [Rect::new(0, 0, 20, 15)]);
+    }
+
+    #[test]
+    fn a_rect_bridging_two_others_merges_all_three() {
+        let mut list = DamageList::new();
+        list.add(Rect::new(0, 0, 10, 10));
+        list.add(Rect::new(30, 0, 10, 10));
+        assert_eq!(list.iter().count(), 2);
+
+        list.add(Rect::new(5, 2, 30, 4));
+        assert_eq!(rects(&list), [Rect::new(0, 0, 40, 10)]);
+    }
+
+    #[test]
+    fn rects_apart_stay_apart() {
+        let mut list = DamageList::new();
+        list.add(Rect::new(0, 0, 10, 10));
+        list.add(Rect::new(11, 0, 10, 10));
+        list.add(Rect::new(0, 11, 10, 10));
+        assert_eq!(list.iter().count(), 3);
+        assert_eq!(list.area(), 300);
+    }
+
+    #[test]
+    fn a_rect_inside_another_changes_nothing() {
+        let mut list = DamageList::new();
+        list.add(Rect::new(0, 0, 100, 100));
+        list.add(Rect::new(200, 0, 10, 10));
+        list.add(Rect::new(10, 10, 20, 20));
+        list.add(Rect::new(0, 0, 100, 100));
+        assert_eq!(rects(&list), [Rect::new(0, 0, 100, 100), Rect::new(200, 0, 10, 10)]);
+    }
+
+    #[test]
+    fn a_rect_covering_another_replaces_it() {
+        let mut list = DamageList::new();
+        list.add(Rect::new(10, 10, 5, 5));
+        list.add(Rect::new(0, 0, 50, 50));
+        assert_eq!(rects(&list), [Rect::new(0, 0, 50, 50)]);
+    }
+
+    #[test]
+    fn overflowing_grows_the_cheapest_rect() {
+        let mut list = DamageList::new();
+        for i in 0..MAX_RECTS {
+            list.add(Rect::new(i * 100, 0, 10, 10));
+        }
+        assert_eq!(list.iter().count(), MAX_RECTS);
+
+        // Five pixels right of the fourth rect, and much further from any other.
+        list.add(Rect::new(315, 0, 10, 10));
+        assert_eq!(list.iter().count(), MAX_RECTS);
+        assert!(list.iter().any(|r| *r == Rect::new(300, 0, 25, 10)));
+        assert_eq!(list.area(), (MAX_RECTS - 1) * 100 + 250);
+    }
+
+    #[test]
+    fn area_counts_every_pixel_once() {
+        const SIZE: usize = 64;
+        // A small linear congruential generator, so the rectangles are the same on every run.
+        let mut seed = 0x2545_f491_u32;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 16) as usize % bound
+        };
+
+        let mut list = DamageList::new();
+        let mut added = Vec::new();
+        for _ in 0..200 {
+            let (x, y) = (next(SIZE - 1), next(SIZE - 1));
+            let rect = Rect::new(x, y, 1 + next(SIZE / 4).min(SIZE - 1 - x), 1 + next(SIZE / 4).min(SIZE - 1 - y));
+            list.add(rect);
+            added.push(rect);
+
+            let kept = coverage(&rects(&list), SIZE);
+            assert!(kept.iter().all(|&count| count <= 1), "rectangles overlap after adding {rect:?}");
+            assert_eq!(list.area(), kept.iter().sum::<usize>());
+            let wanted = coverage(&added, SIZE);
+            assert!(wanted.iter().zip(&kept).all(|(&wanted, &kept)| wanted == 0 || kept == 1));
+        }
+    }
+
+    #[test]
+    fn clearing_forgets_everything() {
+        let mut list = DamageList::new();
+        list.add(Rect::new(0, 0, 10, 10));
+        list.clear();
+        assert_eq!(list.iter().count(), 0);
+        list.add(Rect::new(50, 50, 1, 1));
+        assert_eq!(rects(&list), [Rect::new(50, 50, 1, 1)]);
+    }
+}
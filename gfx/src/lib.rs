@@ -0,0 +1,16 @@
+// Drawing behind the kernel's back buffer: colours and pixel formats, the shape arithmetic, the
+// primitives and the damage tracking. It is kept apart from the framebuffer so all of it can be
+// tested on the host with `cargo test -p gfx`. Like `pong`, nothing in here may depend on
+// `bootloader_api`, `x86_64` or any other hardware-facing crate.
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod canvas;
+pub mod damage;
+pub mod pixel;
+pub mod raster;
+
+pub use canvas::{Canvas, Image};
+pub use damage::{DamageList, Rect};
+pub use pixel::{Color, Encoded, PixelEncoder};
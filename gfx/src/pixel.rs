@@ -0,0 +1,200 @@
+// Colours, and their conversion to and from the raw bytes of whatever pixel layout the firmware
+// picked.
+
+/// A colour with straight (not premultiplied) alpha; 255 is opaque.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b, a: 255 }
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn with_alpha(self, a: u8) -> Self {
+        Self { a, ..self }
+    }
+
+    /// The same colour with its alpha scaled by `coverage`, e.g. a glyph's intensity.
+    pub const fn faded(self, coverage: u8) -> Self {
+        self.with_alpha(mul_div_255(self.a, coverage))
+    }
+
+    pub const fn is_opaque(self) -> bool {
+        self.a == 255
+    }
+
+    /// This colour painted over the opaque `below`.
+    pub fn over(self, below: [u8; 3]) -> [u8; 3] {
+        let mix = |top: u8, bottom: u8| mul_div_255(top, self.a) + mul_div_255(bottom, 255 - self.a);
+        [mix(self.r, below[0]), mix(self.g, below[1]), mix(self.b, below[2])]
+    }
+}
+
+/// `a * b / 255`, rounded.
+const fn mul_div_255(a: u8, b: u8) -> u8 {
+    let product = a as u32 * b as u32 + 128;
+    ((product + (product >> 8)) >> 8) as u8
+}
+
+/// Widest pixel an [Encoded] value can hold.
+pub const MAX_BYTES_PER_PIXEL: usize = 8;
+
+/// One encoded pixel. Only the first `bytes_per_pixel` bytes are meaningful.
+pub type Encoded = [u8; MAX_BYTES_PER_PIXEL];
+
+/// Where one colour channel goes inside the pixel.
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    /// Bits dropped from the 8-bit value to fit the channel's width.
+    drop: u32,
+    /// Bit offset of the channel within the little-endian pixel value.
+    position: u32,
+}
+
+impl Channel {
+    fn place(&self, value: u8) -> u64 {
+        (u64::from(value) >> self.drop) << self.position
+    }
+
+    /// Reads the channel back and stretches it to the full 8-bit range.
+    fn take(&self, pixel: u64) -> u8 {
+        let max = 0xFF_u64 >> self.drop;
+        if max == 0 {
+            return 0;
+        }
+        ((pixel >> self.position & max) * 255 / max) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Layout {
+    /// Separate red, green and blue bit fields.
+    Channels([Channel; 3]),
+    /// A single brightness value repeated in every byte.
+    Gray,
+}
+
+/// Packs colours for one framebuffer mode. The layout is worked out once, so encoding a colour
+/// is just a few shifts.
+#[derive(Debug, Clone, Copy)]
+pub struct PixelEncoder {
+    layout: Layout,
+}
+
+impl PixelEncoder {
+    /// Pixels of `bytes_per_pixel` bytes with bit fields starting at the given offsets for red,
+    /// green and blue. The firmware only reports offsets, so each channel is assumed to reach up
+    /// to the next one or to the end of the pixel, at most 8 bits; 16-bit 5:6:5 modes then come
+    /// out right.
+    pub fn channels(positions: [u8; 3], bytes_per_pixel: usize) -> Self {
+        assert!(
+            (1..=MAX_BYTES_PER_PIXEL).contains(&bytes_per_pixel),
+            "unsupported pixel size of {bytes_per_pixel} bytes"
+        );
+        let pixel_bits = bytes_per_pixel as u32 * 8;
+        let channel = |position: u8| {
+            let position = u32::from(position);
+            let next = positions
+                .iter()
+                .map(|&other| u32::from(other))
+                .filter(|&other| other > position)
+                .min()
+                .unwrap_or(pixel_bits);
+            let width = next.min(pixel_bits).saturating_sub(position).min(8);
+            Channel { drop: 8 - width, position: if width == 0 { 0 } else { position } }
+        };
+        Self { layout: Layout::Channels(positions.map(channel)) }
+    }
+
+    /// Pixels holding a single brightness value, repeated in every byte.
+    pub fn gray() -> Self {
+        Self { layout: Layout::Gray }
+    }
+
+    pub fn encode(&self, r: u8, g: u8, b: u8) -> Encoded {
+        match self.layout {
+            Layout::Channels([red, green, blue]) => {
+                let value = red.place(r) | green.place(g) | blue.place(b);
+                value.to_le_bytes()
+            }
+            Layout::Gray => [luma(r, g, b); MAX_BYTES_PER_PIXEL],
+        }
+    }
+
+    /// Colour of an encoded pixel, as needed to blend something over it.
+    pub fn decode(&self, pixel: &[u8]) -> [u8; 3] {
+        match self.layout {
+            Layout::Channels([red, green, blue]) => {
+                let mut bytes = [0; MAX_BYTES_PER_PIXEL];
+                bytes[..pixel.len()].copy_from_slice(pixel);
+                let value = u64::from_le_bytes(bytes);
+                [red.take(value), green.take(value), blue.take(value)]
+            }
+            Layout::Gray => [pixel[0]; 3],
+        }
+    }
+}
+
+/// Perceived brightness, using the BT.601 weights scaled to 256.
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((u32::from(r) * 77 + u32::from(g) * 150 + u32::from(b) * 29) >> 8) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb_and_bgr_put_the_channels_in_opposite_bytes() {
+        let rgb = PixelEncoder::channels([0, 8, 16], 4);
+        let bgr = PixelEncoder::channels([16, 8, 0], 4);
+        assert_eq!(rgb.encode(1, 2, 3)[..4], [1, 2, 3, 0]);
+        assert_eq!(bgr.encode(1, 2, 3)[..4], [3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn encoding_round_trips_through_decode() {
+        for encoder in [PixelEncoder::channels([0, 8, 16], 4), PixelEncoder::channels([16, 8, 0], 3)] {
+            let pixel = encoder.encode(10, 200, 255);
+            assert_eq!(encoder.decode(&pixel[..3]), [10, 200, 255]);
+        }
+    }
+
+    #[test]
+    fn five_six_five_pixels_keep_the_top_bits() {
+        let encoder = PixelEncoder::channels([11, 5, 0], 2);
+        assert_eq!(u16::from_le_bytes([encoder.encode(255, 0, 0)[0], encoder.encode(255, 0, 0)[1]]), 0xf800);
+        assert_eq!(u16::from_le_bytes([encoder.encode(0, 255, 0)[0], encoder.encode(0, 255, 0)[1]]), 0x07e0);
+        assert_eq!(encoder.decode(&encoder.encode(255, 255, 255)[..2]), [255, 255, 255]);
+    }
+
+    #[test]
+    fn gray_pixels_hold_the_brightness() {
+        let encoder = PixelEncoder::gray();
+        assert_eq!(encoder.encode(255, 255, 255)[0], 255);
+        assert_eq!(encoder.encode(0, 0, 0)[0], 0);
+        assert!(encoder.encode(0, 255, 0)[0] > encoder.encode(255, 0, 0)[0]);
+        assert_eq!(encoder.decode(&[90]), [90; 3]);
+    }
+
+    #[test]
+    fn blending_mixes_by_alpha() {
+        assert_eq!(Color::rgba(200, 100, 0, 255).over([0, 0, 255]), [200, 100, 0]);
+        assert_eq!(Color::rgba(200, 100, 0, 0).over([0, 0, 255]), [0, 0, 255]);
+        assert_eq!(Color::rgba(255, 255, 255, 128).over([0, 0, 0]), [128, 128, 128]);
+        assert_eq!(Color::WHITE.faded(0).a, 0);
+        assert_eq!(Color::WHITE.faded(255).a, 255);
+    }
+}
@@ -0,0 +1,231 @@
+// Pixel arithmetic behind the drawing primitives in `canvas`: clipping shapes to the screen,
+// Bresenham lines and the rows and arcs of circles.
+
+use crate::damage::Rect;
+
+/// The part of the box at `(x, y)` with the given size that lies on a `screen_width` by
+/// `screen_height` screen. Boxes entirely off screen or without area clip to an empty rectangle.
+pub fn clip(x: isize, y: isize, width: isize, height: isize, screen_width: usize, screen_height: usize) -> Rect {
+    let (screen_width, screen_height) = (screen_width as isize, screen_height as isize);
+    let left = x.clamp(0, screen_width);
+    let top = y.clamp(0, screen_height);
+    let right = x.saturating_add(width).clamp(left, screen_width);
+    let bottom = y.saturating_add(height).clamp(top, screen_height);
+    Rect::new(left as usize, top as usize, (right - left) as usize, (bottom - top) as usize)
+}
+
+/// Columns `start..end`, counted from the left edge of a `width` by `height` box whose corners
+/// are quarter circles of `radius`, that row `row` of the box covers. A radius too large for the
+/// box is shrunk to fit, so a square with a large radius is a circle.
+pub fn rounded_row(row: usize, width: usize, height: usize, radius: usize) -> (usize, usize) {
+    let radius = radius.min(width / 2).min(height / 2);
+    // Rows level with a corner's centre and beyond it are not inset.
+    let from_edge = row.min(height.saturating_sub(row + 1));
+    let inset = if from_edge < radius {
+        radius - circle_half_width(radius, radius - from_edge)
+    } else {
+        0
+    };
+    (inset, width - inset)
+}
+
+/// Half the width of the row `dy` pixels away from the centre of a circle of `radius`, that is
+/// the largest `x` with `x² + dy² <= radius²`.
+pub fn circle_half_width(radius: usize, dy: usize) -> usize {
+    let limit = radius * radius - dy * dy;
+    let mut x = 0;
+    while (x + 1) * (x + 1) <= limit {
+        x += 1;
+    }
+    x
+}
+
+/// The pixels of a one pixel wide line between two points, both included, by Bresenham's
+/// algorithm. Each pixel is next to the one before, sideways or diagonally.
+pub struct Line {
+    at: (isize, isize),
+    to: (isize, isize),
+    dx: isize,
+    dy: isize,
+    step: (isize, isize),
+    error: isize,
+    done: bool,
+}
+
+impl Line {
+    pub fn new(from: (isize, isize), to: (isize, isize)) -> Self {
+        let (dx, dy) = ((to.0 - from.0).abs(), -(to.1 - from.1).abs());
+        Self {
+            at: from,
+            to,
+            dx,
+            dy,
+            step: ((to.0 - from.0).signum(), (to.1 - from.1).signum()),
+            error: dx + dy,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for Line {
+    type Item = (isize, isize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let point = self.at;
+        if point == self.to {
+            self.done = true;
+            return Some(point);
+        }
+        let doubled = 2 * self.error;
+        if doubled >= self.dy {
+            self.error += self.dy;
+            self.at.0 += self.step.0;
+        }
+        if doubled <= self.dx {
+            self.error += self.dx;
+            self.at.1 += self.step.1;
+        }
+        Some(point)
+    }
+}
+
+/// One eighth of the outline of a circle of `radius` around the origin, by the midpoint circle
+/// algorithm: the offsets `(x, y)` with `x >= y >= 0`, from `(radius, 0)` up to the diagonal.
+/// Mirroring each one across the axes and the diagonals gives the whole outline.
+pub struct CircleOctant {
+    x: isize,
+    y: isize,
+    error: isize,
+}
+
+impl CircleOctant {
+    pub fn new(radius: usize) -> Self {
+        let radius = radius as isize;
+        Self { x: radius, y: 0, error: 1 - radius }
+    }
+}
+
+impl Iterator for CircleOctant {
+    type Item = (isize, isize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.x < self.y {
+            return None;
+        }
+        let point = (self.x, self.y);
+        self.y += 1;
+        if self.error < 0 {
+            self.error += 2 * self.y + 1;
+        } else {
+            self.x -= 1;
+            self.error += 2 * (self.y - self.x) + 1;
+        }
+        Some(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxes_on_screen_are_left_alone() {
+        assert_eq!(clip(10, 20, 30, 40, 640, 480), Rect::new(10, 20, 30, 40));
+        assert_eq!(clip(0, 0, 640, 480, 640, 480), Rect::new(0, 0, 640, 480));
+    }
+
+    #[test]
+    fn boxes_are_clipped_at_every_edge() {
+        assert_eq!(clip(-5, 10, 20, 10, 640, 480), Rect::new(0, 10, 15, 10));
+        assert_eq!(clip(10, -5, 10, 20, 640, 480), Rect::new(10, 0, 10, 15));
+        assert_eq!(clip(630, 10, 20, 10, 640, 480), Rect::new(630, 10, 10, 10));
+        assert_eq!(clip(10, 470, 10, 20, 640, 480), Rect::new(10, 470, 10, 10));
+        assert_eq!(clip(-10, -10, 1000, 1000, 640, 480), Rect::new(0, 0, 640, 480));
+    }
+
+    #[test]
+    fn boxes_off_screen_or_without_area_clip_to_nothing() {
+        assert!(clip(-20, 10, 20, 10, 640, 480).is_empty());
+        assert!(clip(10, -20, 10, 20, 640, 480).is_empty());
+        assert!(clip(640, 10, 20, 10, 640, 480).is_empty());
+        assert!(clip(10, 480, 10, 20, 640, 480).is_empty());
+        assert!(clip(10, 10, 0, 10, 640, 480).is_empty());
+        assert!(clip(10, 10, -5, 10, 640, 480).is_empty());
+        assert!(clip(0, 0, 10, 10, 0, 0).is_empty());
+        assert!(clip(isize::MAX, isize::MAX, isize::MAX, isize::MAX, 640, 480).is_empty());
+    }
+
+    #[test]
+    fn square_corners_cover_whole_rows() {
+        for row in 0..4 {
+            assert_eq!(rounded_row(row, 10, 4, 0), (0, 10));
+        }
+    }
+
+    #[test]
+    fn rounded_rows_are_inset_near_the_top_and_bottom_only() {
+        let rows: [(usize, usize); 7] = core::array::from_fn(|row| rounded_row(row, 20, 7, 2));
+        assert_eq!(rows, [(2, 18), (1, 19), (0, 20), (0, 20), (0, 20), (1, 19), (2, 18)]);
+    }
+
+    #[test]
+    fn a_large_radius_on_a_square_makes_a_circle() {
+        let rows: [(usize, usize); 5] = core::array::from_fn(|row| rounded_row(row, 5, 5, 100));
+        assert_eq!(rows, [(2, 3), (1, 4), (0, 5), (1, 4), (2, 3)]);
+        assert_eq!(rounded_row(0, 1, 1, 0), (0, 1));
+    }
+
+    #[test]
+    fn circle_rows_stay_inside_the_radius() {
+        assert_eq!(circle_half_width(5, 0), 5);
+        assert_eq!(circle_half_width(5, 3), 4);
+        assert_eq!(circle_half_width(5, 5), 0);
+        assert_eq!(circle_half_width(0, 0), 0);
+    }
+
+    #[test]
+    fn lines_include_both_ends_and_have_no_gaps() {
+        for (from, to) in [((0, 0), (7, 3)), ((7, 3), (0, 0)), ((2, -4), (-1, 5)), ((0, 0), (0, 6)), ((3, 3), (-3, -3))] {
+            let points: Vec<_> = Line::new(from, to).collect();
+            assert_eq!(points.first(), Some(&from));
+            assert_eq!(points.last(), Some(&to));
+            let (dx, dy) = ((to.0 - from.0).abs(), (to.1 - from.1).abs());
+            assert_eq!(points.len() as isize, dx.max(dy) + 1);
+            for pair in points.windows(2) {
+                assert!((pair[1].0 - pair[0].0).abs() <= 1 && (pair[1].1 - pair[0].1).abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn a_line_to_itself_is_one_pixel() {
+        assert_eq!(Line::new((4, -2), (4, -2)).collect::<Vec<_>>(), [(4, -2)]);
+    }
+
+    #[test]
+    fn horizontal_and_diagonal_lines_are_straight() {
+        assert_eq!(Line::new((0, 2), (3, 2)).collect::<Vec<_>>(), [(0, 2), (1, 2), (2, 2), (3, 2)]);
+        assert_eq!(Line::new((0, 0), (-2, 2)).collect::<Vec<_>>(), [(0, 0), (-1, 1), (-2, 2)]);
+    }
+
+    #[test]
+    fn circle_octants_run_from_the_axis_to_the_diagonal() {
+        assert_eq!(CircleOctant::new(0).collect::<Vec<_>>(), [(0, 0)]);
+        assert_eq!(CircleOctant::new(3).collect::<Vec<_>>(), [(3, 0), (3, 1), (2, 2)]);
+        for radius in 1..40 {
+            let points: Vec<_> = CircleOctant::new(radius).collect();
+            let r = radius as isize;
+            assert_eq!(points[0], (r, 0));
+            for &(x, y) in &points {
+                assert!(x >= y && (x * x + y * y - r * r).abs() <= r, "{x},{y} is off a circle of radius {r}");
+            }
+            for pair in points.windows(2) {
+                assert_eq!(pair[1].1, pair[0].1 + 1);
+                assert!(pair[0].0 - pair[1].0 <= 1);
+            }
+        }
+    }
+}
@@ -3,14 +3,17 @@ static ALLOCATOR: Allocator = Allocator;
 
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr::null_mut;
-use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::trace;
 
-use crate::serial;
 pub struct Allocator;
 
 pub static mut HEAP_START: usize = 0x0;
 pub static mut OFFSET: usize = 0x0;
-pub const HEAP_SIZE: usize = 10000 * 1024; // 100 KiB
+/// Set once by [init_heap], before anything is allocated.
+static HEAP_SIZE: AtomicUsize = AtomicUsize::new(0);
+/// Heap on top of what the screen needs, for everything else the kernel allocates.
+pub const HEAP_SLACK: usize = 1024 * 1024; // 1 MiB
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
@@ -20,7 +23,7 @@ unsafe impl GlobalAlloc for Allocator {
         let alloc_start = (HEAP_START + OFFSET + align - 1) & !(align - 1);
         let alloc_end = alloc_start + size;
 
-        if alloc_end > HEAP_START + HEAP_SIZE {
+        if alloc_end > HEAP_START + HEAP_SIZE.load(Ordering::Relaxed) {
             return null_mut();
         }
 
@@ -29,13 +32,19 @@ unsafe impl GlobalAlloc for Allocator {
     }
 
     unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        writeln!(serial(), "dealloc was called at {_ptr:?}").unwrap();
+        trace!("dealloc was called at {_ptr:?}");
     }
 }
 
-pub fn init_heap(offset: usize) {
+pub fn init_heap(offset: usize, size: usize) {
+    HEAP_SIZE.store(size, Ordering::Relaxed);
     unsafe {
         HEAP_START = offset;
         OFFSET = 0; 
     }
-}
\ No newline at end of file
+}
+
+/// Bytes handed out so far and the size of the heap. Nothing is ever given back.
+pub fn usage() -> (usize, usize) {
+    (unsafe { OFFSET }, HEAP_SIZE.load(Ordering::Relaxed))
+}
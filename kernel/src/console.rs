@@ -0,0 +1,434 @@
+// On-screen text console for kernel messages. Text is kept as a scrollback of coloured cells and
+// rendered into an off-screen panel, which the game loop lays over the game while it is open.
+
+use core::fmt::{self, Write};
+use alloc::vec;
+use alloc::vec::Vec;
+use noto_sans_mono_bitmap::{get_raster, get_raster_width, FontWeight, RasterHeight};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use gfx::{Color, PixelEncoder, Rect};
+use crate::screen::ScreenWriter;
+use kernel::logger::Level;
+
+static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+
+/// Writes to the console; text written before [init] is dropped. Safe to use from interrupt
+/// handlers, the console is only ever locked with interrupts off.
+pub struct Writer;
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        without_interrupts(|| {
+            if let Some(console) = CONSOLE.lock().as_mut() {
+                console.write_str(s);
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Sets up a console as wide as the screen covering its top half, hidden.
+pub fn init(writer: &ScreenWriter) {
+    let console = Console::new(writer.width(), rows(writer.height()), writer.encoder(), writer.bytes_per_pixel());
+    without_interrupts(|| *CONSOLE.lock() = Some(console));
+}
+
+/// Heap bytes [init] takes for a screen of this size.
+pub fn heap_needed(width: usize, height: usize, bytes_per_pixel: usize) -> usize {
+    let panel = width * rows(height) * RASTER.val() * bytes_per_pixel;
+    let scrollback = columns(width) * SCROLLBACK_LINES * core::mem::size_of::<Cell>();
+    panel + scrollback
+}
+
+/// Text rows that fit the top half of a screen this high.
+fn rows(height: usize) -> usize {
+    (height / 2 / RASTER.val()).max(1)
+}
+
+/// Characters that fit a line this wide.
+fn columns(width: usize) -> usize {
+    (width / get_raster_width(FontWeight::Regular, RASTER)).max(1)
+}
+
+/// Shows the console if it is hidden and hides it otherwise.
+pub fn toggle() {
+    with_console(|console| console.visible = !console.visible);
+}
+
+/// Scrolls the view back into the history by `lines`, or towards the newest text if negative.
+pub fn scroll(lines: isize) {
+    with_console(|console| console.scroll(lines));
+}
+
+/// Draws the console over whatever is in the back buffer, if it is open.
+pub fn draw(writer: &mut ScreenWriter) {
+    with_console(|console| {
+        if console.visible {
+            console.panel.draw(writer);
+        }
+    });
+}
+
+fn with_console(f: impl FnOnce(&mut Console)) {
+    without_interrupts(|| {
+        if let Some(console) = CONSOLE.lock().as_mut() {
+            f(console);
+        }
+    });
+}
+
+const RASTER: RasterHeight = RasterHeight::Size16;
+/// Lines kept for scrolling back, including the ones on screen.
+const SCROLLBACK_LINES: usize = 500;
+const TAB_WIDTH: usize = 8;
+/// Text colour when no escape code has set one.
+const FOREGROUND: Color = Color::rgb(63, 255, 127);
+const BACKGROUND: Color = Color::rgb(12, 12, 20);
+/// Line drawn under the panel to set it apart from the game.
+const BORDER: Color = Color::rgb(63, 255, 127);
+
+/// The 16 ANSI colours: the eight normal ones, then their bright variants.
+const PALETTE: [Color; 16] = [
+    Color::rgb(0, 0, 0),
+    Color::rgb(170, 0, 0),
+    Color::rgb(0, 170, 0),
+    Color::rgb(170, 85, 0),
+    Color::rgb(0, 0, 170),
+    Color::rgb(170, 0, 170),
+    Color::rgb(0, 170, 170),
+    Color::rgb(170, 170, 170),
+    Color::rgb(85, 85, 85),
+    Color::rgb(255, 85, 85),
+    Color::rgb(85, 255, 85),
+    Color::rgb(255, 255, 85),
+    Color::rgb(85, 85, 255),
+    Color::rgb(255, 85, 255),
+    Color::rgb(85, 255, 255),
+    Color::rgb(255, 255, 255),
+];
+
+/// Look of a character cell, as set by SGR escape codes. Colours are palette indices, or
+/// [Attr::DEFAULT_COLOR] for the console's own colours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Attr {
+    fg: u8,
+    bg: u8,
+    bold: bool,
+}
+
+impl Attr {
+    const DEFAULT_COLOR: u8 = u8::MAX;
+    const DEFAULT: Attr = Attr { fg: Self::DEFAULT_COLOR, bg: Self::DEFAULT_COLOR, bold: false };
+
+    fn foreground(&self) -> Color {
+        PALETTE.get(usize::from(self.fg)).copied().unwrap_or(FOREGROUND)
+    }
+
+    fn background(&self) -> Color {
+        PALETTE.get(usize::from(self.bg)).copied().unwrap_or(BACKGROUND)
+    }
+
+    fn weight(&self) -> FontWeight {
+        if self.bold { FontWeight::Bold } else { FontWeight::Regular }
+    }
+
+    /// Applies one SGR parameter. Codes for effects the console cannot show are ignored.
+    fn apply(&mut self, code: u16) {
+        match code {
+            0 => *self = Attr::DEFAULT,
+            1 => self.bold = true,
+            22 => self.bold = false,
+            30..=37 => self.fg = (code - 30) as u8,
+            39 => self.fg = Self::DEFAULT_COLOR,
+            40..=47 => self.bg = (code - 40) as u8,
+            49 => self.bg = Self::DEFAULT_COLOR,
+            90..=97 => self.fg = (code - 90 + 8) as u8,
+            100..=107 => self.bg = (code - 100 + 8) as u8,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    c: char,
+    attr: Attr,
+}
+
+const BLANK: Cell = Cell { c: ' ', attr: Attr::DEFAULT };
+
+/// Most parameters kept from one escape sequence; further ones are dropped.
+const MAX_PARAMS: usize = 8;
+
+/// Progress through an escape sequence.
+#[derive(Debug, Clone, Copy)]
+enum Escape {
+    None,
+    /// Just seen ESC.
+    Started,
+    /// Inside `ESC [`, collecting `;`-separated numbers up to the final byte.
+    Csi { params: [u16; MAX_PARAMS], len: usize },
+}
+
+/// The last [SCROLLBACK_LINES] lines of text, oldest first, in a ring so that adding a line
+/// never moves the others.
+struct Scrollback {
+    cells: Vec<Cell>,
+    columns: usize,
+    /// Ring slot of the oldest line.
+    first: usize,
+    lines: usize,
+}
+
+impl Scrollback {
+    fn new(columns: usize) -> Self {
+        Self { cells: vec![BLANK; columns * SCROLLBACK_LINES], columns, first: 0, lines: 1 }
+    }
+
+    /// Line `index`, counting from the oldest one kept.
+    fn line(&self, index: usize) -> &[Cell] {
+        let slot = (self.first + index) % SCROLLBACK_LINES;
+        &self.cells[slot * self.columns..(slot + 1) * self.columns]
+    }
+
+    fn last_mut(&mut self) -> &mut [Cell] {
+        let slot = (self.first + self.lines - 1) % SCROLLBACK_LINES;
+        &mut self.cells[slot * self.columns..(slot + 1) * self.columns]
+    }
+
+    /// Starts a new, blank line, forgetting the oldest one when full.
+    fn push_line(&mut self) {
+        if self.lines == SCROLLBACK_LINES {
+            self.first = (self.first + 1) % SCROLLBACK_LINES;
+        } else {
+            self.lines += 1;
+        }
+        self.last_mut().fill(BLANK);
+    }
+}
+
+/// Rendered view of the console, stored already encoded for the screen so showing it is a
+/// plain copy.
+struct Panel {
+    pixels: Vec<u8>,
+    encoder: PixelEncoder,
+    bytes_per_pixel: usize,
+    width: usize,
+    rows: usize,
+    columns: usize,
+    glyph_width: usize,
+    line_height: usize,
+}
+
+impl Panel {
+    fn new(width: usize, rows: usize, encoder: PixelEncoder, bytes_per_pixel: usize) -> Self {
+        let glyph_width = get_raster_width(FontWeight::Regular, RASTER);
+        let line_height = RASTER.val();
+        let mut panel = Self {
+            pixels: vec![0; width * rows * line_height * bytes_per_pixel],
+            encoder,
+            bytes_per_pixel,
+            width,
+            rows,
+            columns: columns(width),
+            glyph_width,
+            line_height,
+        };
+        for row in 0..rows {
+            panel.blank_row(row);
+        }
+        panel
+    }
+
+    fn height(&self) -> usize {
+        self.rows * self.line_height
+    }
+
+    fn row_bytes(&self) -> usize {
+        self.width * self.line_height * self.bytes_per_pixel
+    }
+
+    /// Moves every text row up by one, copying the pixels, and blanks the bottom row.
+    fn scroll_up(&mut self) {
+        let row_bytes = self.row_bytes();
+        self.pixels.copy_within(row_bytes.., 0);
+        self.blank_row(self.rows - 1);
+    }
+
+    fn blank_row(&mut self, row: usize) {
+        let row_bytes = self.row_bytes();
+        let background = self.encoder.encode(BACKGROUND.r, BACKGROUND.g, BACKGROUND.b);
+        for pixel in self.pixels[row * row_bytes..(row + 1) * row_bytes].chunks_exact_mut(self.bytes_per_pixel) {
+            pixel.copy_from_slice(&background[..self.bytes_per_pixel]);
+        }
+    }
+
+    fn draw_line(&mut self, row: usize, line: &[Cell]) {
+        self.blank_row(row);
+        for (column, cell) in line.iter().enumerate() {
+            if cell.c != ' ' || cell.attr.bg != Attr::DEFAULT_COLOR {
+                self.draw_cell(row, column, *cell);
+            }
+        }
+    }
+
+    fn draw_cell(&mut self, row: usize, column: usize, cell: Cell) {
+        let fg = cell.attr.foreground();
+        let bg = cell.attr.background();
+        let glyph = get_raster(cell.c, cell.attr.weight(), RASTER).or_else(|| get_raster('?', cell.attr.weight(), RASTER));
+        for y in 0..self.line_height {
+            let raster_row = glyph.as_ref().and_then(|glyph| glyph.raster().get(y));
+            for x in 0..self.glyph_width {
+                let intensity = raster_row.and_then(|pixels| pixels.get(x)).copied().unwrap_or(0);
+                let [r, g, b] = fg.faded(intensity).over([bg.r, bg.g, bg.b]);
+                let encoded = self.encoder.encode(r, g, b);
+                let at = ((row * self.line_height + y) * self.width + column * self.glyph_width + x) * self.bytes_per_pixel;
+                self.pixels[at..at + self.bytes_per_pixel].copy_from_slice(&encoded[..self.bytes_per_pixel]);
+            }
+        }
+    }
+
+    fn draw(&self, writer: &mut ScreenWriter) {
+        writer.copy_pixels(Rect::new(0, 0, self.width, self.height()), &self.pixels);
+        writer.fill_rect(Rect::new(0, self.height(), self.width, 2), BORDER);
+    }
+}
+
+/// Terminal-like text output: wraps long lines, scrolls and understands the ANSI colour codes.
+struct Console {
+    scrollback: Scrollback,
+    panel: Panel,
+    /// Where the next character goes on the newest line.
+    column: usize,
+    attr: Attr,
+    escape: Escape,
+    /// How many lines the view is scrolled back from the newest one.
+    scrolled: usize,
+    visible: bool,
+}
+
+impl Console {
+    fn new(width: usize, rows: usize, encoder: PixelEncoder, bytes_per_pixel: usize) -> Self {
+        let panel = Panel::new(width, rows, encoder, bytes_per_pixel);
+        Self {
+            scrollback: Scrollback::new(panel.columns),
+            panel,
+            column: 0,
+            attr: Attr::DEFAULT,
+            escape: Escape::None,
+            scrolled: 0,
+            visible: false,
+        }
+    }
+
+    fn write_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        self.escape = match (self.escape, c) {
+            (Escape::None, '\x1b') => Escape::Started,
+            (Escape::None, c) => {
+                self.print(c);
+                Escape::None
+            }
+            (Escape::Started, '[') => Escape::Csi { params: [0; MAX_PARAMS], len: 1 },
+            (Escape::Started, _) => Escape::None,
+            (Escape::Csi { mut params, len }, '0'..='9') => {
+                if let Some(param) = params.get_mut(len - 1) {
+                    *param = param.saturating_mul(10).saturating_add(c as u16 - '0' as u16);
+                }
+                Escape::Csi { params, len }
+            }
+            (Escape::Csi { params, len }, ';') => Escape::Csi { params, len: len + 1 },
+            (Escape::Csi { params, len }, '\x40'..='\x7e') => {
+                // Only colours are supported; cursor movement and erasing are skipped.
+                if c == 'm' {
+                    for &code in &params[..len.min(MAX_PARAMS)] {
+                        self.attr.apply(code);
+                    }
+                }
+                Escape::None
+            }
+            (escape @ Escape::Csi { .. }, _) => escape,
+        };
+    }
+
+    fn print(&mut self, c: char) {
+        match c {
+            '\n' => self.newline(),
+            '\r' => self.column = 0,
+            '\t' => {
+                let stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.column < stop.min(self.scrollback.columns) {
+                    self.print(' ');
+                }
+            }
+            '\x08' => self.column = self.column.saturating_sub(1),
+            c if c.is_control() => {}
+            c => {
+                if self.column == self.scrollback.columns {
+                    self.newline();
+                }
+                let cell = Cell { c, attr: self.attr };
+                self.scrollback.last_mut()[self.column] = cell;
+                if self.scrolled == 0 {
+                    self.panel.draw_cell(self.panel.rows - 1, self.column, cell);
+                }
+                self.column += 1;
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        self.scrollback.push_line();
+        self.column = 0;
+        if self.scrolled == 0 {
+            self.panel.scroll_up();
+        } else if self.scrolled < self.max_scroll() {
+            // Keep showing the same lines while reading back.
+            self.scrolled += 1;
+        } else {
+            self.redraw();
+        }
+    }
+
+    fn max_scroll(&self) -> usize {
+        self.scrollback.lines.saturating_sub(self.panel.rows)
+    }
+
+    fn scroll(&mut self, lines: isize) {
+        let scrolled = self.scrolled.saturating_add_signed(lines).min(self.max_scroll());
+        if scrolled != self.scrolled {
+            self.scrolled = scrolled;
+            self.redraw();
+        }
+    }
+
+    /// Renders every row of the panel from the scrollback.
+    fn redraw(&mut self) {
+        // Row `rows - 1` shows the newest line less however far the view is scrolled back.
+        let bottom = self.scrollback.lines - 1 - self.scrolled;
+        for row in 0..self.panel.rows {
+            match (bottom + row + 1).checked_sub(self.panel.rows) {
+                Some(index) => self.panel.draw_line(row, self.scrollback.line(index)),
+                None => self.panel.blank_row(row),
+            }
+        }
+    }
+}
+
+/// Logger sink showing each line in the console, coloured by its level.
+pub fn log_sink(level: Level, line: &str) {
+    let color = match level {
+        Level::Error => "\x1b[91m",
+        Level::Warn => "\x1b[93m",
+        Level::Info => "",
+        Level::Debug => "\x1b[36m",
+        Level::Trace => "\x1b[90m",
+    };
+    let _ = writeln!(Writer, "{color}{line}\x1b[0m");
+}
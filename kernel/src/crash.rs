@@ -0,0 +1,163 @@
+// What the panic handler reports. The message and a backtrace go to COM1, then a screen
+// installed with [set_screen] gets to show them. The backtrace follows the chain of saved frame
+// pointers, which `.cargo/config.toml` makes every kernel function keep. Fatal exceptions panic on
+// a stack of their own, where that chain ends at the interrupt entry, so their backtrace carries
+// on from the interrupted code's instruction and frame pointer.
+//
+// The serial report is meant to be read by tools as well as people:
+//
+//     PANIC: panicked at kernel/src/main.rs:120:5:
+//     the message
+//     BACKTRACE: kernel image offset 0xffff800000000000
+//     BACKTRACE: #0 0xffff80000001a2b3
+//     BACKTRACE: #1 0xffff800000013c4d
+//     BACKTRACE: #2 0xffff800000012345 interrupted
+//     BACKTRACE: #3 0xffff800000011f00
+//     BACKTRACE: end
+//
+// Each address is a return address, the instruction after a call, as loaded in memory, except the
+// one marked `interrupted`: that is the instruction an exception stopped. Minus the kernel image
+// offset an address is an address in the kernel ELF file.
+
+use core::arch::asm;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Once;
+use crate::{exceptions, serial};
+use crate::trap::TrapFrame;
+
+/// Shows a panic to the user, after it is reported on the serial port. It runs with interrupts
+/// off and the rest of the kernel stopped wherever it was, so it must not wait for locks.
+pub type Screen = fn(&PanicInfo, &Backtrace);
+
+/// Frames a backtrace goes up at most.
+const MAX_FRAMES: usize = 32;
+/// How far above its first frame a walk looks for callers on a stack that was not registered
+/// with [exceptions::add_stack]; no kernel stack is larger.
+const MAX_STACK_SPAN: u64 = 1024 * 1024;
+
+static SCREEN: Once<Screen> = Once::new();
+/// Where the bootloader loaded the kernel, relative to the addresses in its ELF file.
+static KERNEL_IMAGE_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// Set by the first panic, so one while reporting it does not start over.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+/// rip and rbp of the code the fatal exception being reported interrupted.
+static INTERRUPTED: Once<(u64, u64)> = Once::new();
+
+/// Records where the kernel was loaded, `BootInfo::kernel_image_offset`, for the reports.
+pub fn init(kernel_image_offset: u64) {
+    KERNEL_IMAGE_OFFSET.store(kernel_image_offset, Ordering::Relaxed);
+}
+
+/// Installs the screen panics are shown on. Only the first call counts.
+pub fn set_screen(screen: Screen) {
+    SCREEN.call_once(|| screen);
+}
+
+pub fn kernel_image_offset() -> u64 {
+    KERNEL_IMAGE_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Records where a fatal exception interrupted the kernel, right before it panics, for the
+/// backtrace to carry on from.
+pub(crate) fn set_interrupted(frame: &TrapFrame) {
+    INTERRUPTED.call_once(|| (frame.rip, frame.rbp));
+}
+
+/// Return addresses of the calls that led to where it was captured, innermost first.
+pub struct Backtrace {
+    addresses: [u64; MAX_FRAMES],
+    len: usize,
+    /// Index of the instruction an exception interrupted, the one address that is not a return
+    /// address.
+    interrupted: Option<usize>,
+}
+
+impl Backtrace {
+    /// Walks the frame pointer chain from the caller's frame.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let frame: u64;
+        // SAFETY: only copies rbp.
+        unsafe { asm!("mov {}, rbp", out(reg) frame, options(nomem, nostack, preserves_flags)) };
+
+        let mut backtrace = Self { addresses: [0; MAX_FRAMES], len: 0, interrupted: None };
+        backtrace.walk(frame);
+        backtrace
+    }
+
+    /// Adds the instruction at `rip`, which an exception interrupted, and the callers of its
+    /// function, found from the frame pointer `rbp` it had. Only a registered stack is walked:
+    /// the exception may have hit code that keeps something else in rbp.
+    fn continue_from(&mut self, rip: u64, rbp: u64) {
+        if self.len == MAX_FRAMES {
+            return;
+        }
+        self.interrupted = Some(self.len);
+        self.addresses[self.len] = rip;
+        self.len += 1;
+        if exceptions::stack_containing(rbp).is_some() {
+            self.walk(rbp);
+        }
+    }
+
+    /// Follows the chain from `frame` up its stack. The walk stops at the first frame pointer
+    /// that does not lead further up the same stack, so a corrupted stack gives a short
+    /// backtrace rather than a fault.
+    fn walk(&mut self, mut frame: u64) {
+        let stack_limit = exceptions::stack_containing(frame)
+            .map_or(frame.saturating_add(MAX_STACK_SPAN), |stack| stack.top.as_u64());
+        while self.len < MAX_FRAMES && frame != 0 && frame % 8 == 0 && frame.saturating_add(16) <= stack_limit {
+            // SAFETY: a frame pointer points at the caller's saved frame pointer, followed by the
+            // return address, and the frames checked so far all lie in the stack above the
+            // first one.
+            let [caller_frame, return_address] = unsafe { (frame as *const [u64; 2]).read() };
+            if return_address == 0 {
+                break;
+            }
+            self.addresses[self.len] = return_address;
+            self.len += 1;
+            // Callers' frames are higher up the stack.
+            if caller_frame <= frame {
+                break;
+            }
+            frame = caller_frame;
+        }
+    }
+
+    pub fn addresses(&self) -> &[u64] {
+        &self.addresses[..self.len]
+    }
+
+    /// Whether the address at `index` is an interrupted instruction rather than a return address.
+    pub fn is_interrupted(&self, index: usize) -> bool {
+        self.interrupted == Some(index)
+    }
+}
+
+/// Reports a panic on the serial port and the installed screen. Called by the panic handler
+/// with interrupts off.
+pub(crate) fn report(info: &PanicInfo) {
+    let mut backtrace = Backtrace::capture();
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        // The report itself panicked; the port is already taken over.
+        let _ = writeln!(serial::Writer, "PANIC while reporting a panic: {info}");
+        return;
+    }
+    if let Some(&(rip, rbp)) = INTERRUPTED.get() {
+        backtrace.continue_from(rip, rbp);
+    }
+    serial::take_over_for_panic();
+    let _ = writeln!(serial::Writer, "PANIC: {info}");
+    let _ = writeln!(serial::Writer, "BACKTRACE: kernel image offset {:#x}", kernel_image_offset());
+    for (index, address) in backtrace.addresses().iter().enumerate() {
+        let interrupted = if backtrace.is_interrupted(index) { " interrupted" } else { "" };
+        let _ = writeln!(serial::Writer, "BACKTRACE: #{index} {address:#x}{interrupted}");
+    }
+    let _ = writeln!(serial::Writer, "BACKTRACE: end");
+
+    if let Some(screen) = SCREEN.get() {
+        screen(info, &backtrace);
+    }
+}
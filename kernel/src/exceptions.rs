@@ -0,0 +1,287 @@
+// Handlers for the CPU exception vectors. Breakpoints and debug exceptions belong to the GDB stub;
+// every other exception is fatal: the kernel panics with the decoded error code and the
+// registers, which the panic report shows on the serial port and on screen.
+//
+// Double faults, NMIs and machine checks run on stacks of their own from the TSS's interrupt stack
+// table, so they are handled even when the interrupted code has used up its stack. Page faults
+// stay on the interrupted stack: a page fault in their handler would start over at the top of a
+// stack of its own and overwrite the frames still in use there. Stacks registered with
+// [add_stack] have an unmapped guard page below them, and an exception caused by running into
+// one is reported as a stack overflow. That is usually a double fault: the page fault's frame
+// cannot be pushed onto the full stack either.
+
+use core::fmt;
+use core::ops::Range;
+use log::warn;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::registers::control::Cr2;
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::idt::{Entry, EntryOptions, InterruptDescriptorTable, PageFaultErrorCode, SelectorErrorCode};
+use x86_64::structures::paging::{PageSize, Size4KiB};
+use x86_64::VirtAddr;
+use crate::{crash, gdb};
+use crate::trap::{trap_entry, TrapFrame};
+
+/// Interrupt stack table slots of the exceptions that get a stack of their own. The TSS has to
+/// have a stack in each before the IDT is loaded.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+/// Stacks [add_stack] keeps track of at most.
+const MAX_STACKS: usize = 8;
+
+static STACKS: Mutex<[Option<Stack>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+/// A kernel stack, growing down from `top` to `bottom`, with an unmapped guard page right below.
+#[derive(Debug, Clone, Copy)]
+pub struct Stack {
+    /// What the stack is for, like `double fault`; reports call it "the {name} stack".
+    pub name: &'static str,
+    pub bottom: VirtAddr,
+    pub top: VirtAddr,
+}
+
+impl Stack {
+    fn guard_page(&self) -> Range<u64> {
+        self.bottom.as_u64().saturating_sub(Size4KiB::SIZE)..self.bottom.as_u64()
+    }
+}
+
+/// Registers `stack`, so that running into its guard page is reported as a stack overflow.
+/// Stacks past the first [MAX_STACKS] are ignored.
+pub fn add_stack(stack: Stack) {
+    without_interrupts(|| {
+        if let Some(slot) = STACKS.lock().iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(stack);
+        }
+    });
+}
+
+/// The registered stack `address` is in, if any.
+pub(crate) fn stack_containing(address: u64) -> Option<Stack> {
+    let stacks = STACKS.try_lock()?;
+    stacks.iter().flatten().find(|stack| (stack.bottom.as_u64()..stack.top.as_u64()).contains(&address)).copied()
+}
+
+/// The registered stack whose guard page `address` is in.
+fn guarded_stack(address: u64) -> Option<Stack> {
+    // A fault while registering a stack is not an overflow worth waiting for.
+    let stacks = STACKS.try_lock()?;
+    stacks.iter().flatten().find(|stack| stack.guard_page().contains(&address)).copied()
+}
+
+/// A CPU exception the kernel cannot recover from.
+#[derive(Debug, Clone, Copy)]
+pub struct Exception {
+    pub frame: TrapFrame,
+    /// The address that could not be accessed, for page faults. For double faults, that of the
+    /// last page fault, which is in a guard page if running into it led to the double fault.
+    pub address: Option<u64>,
+    /// The stack that overflowed, if the exception came from running into its guard page.
+    pub overflowed: Option<Stack>,
+}
+
+impl Exception {
+    /// Finds out whether the exception in `frame` is a stack overflow: either the interrupted
+    /// code's stack pointer or, for page and double faults, the address that could not be
+    /// accessed is in a guard page.
+    pub fn new(frame: TrapFrame, address: Option<u64>) -> Self {
+        let overflowed = guarded_stack(frame.rsp).or_else(|| address.and_then(guarded_stack));
+        Self { frame, address, overflowed }
+    }
+
+    pub fn vector(&self) -> u8 {
+        self.frame.vector as u8
+    }
+
+    pub fn name(&self) -> &'static str {
+        if self.overflowed.is_some() {
+            return "STACK OVERFLOW";
+        }
+        describe(self.vector()).1
+    }
+
+    /// The short name the manuals use, like `#GP`.
+    pub fn mnemonic(&self) -> &'static str {
+        describe(self.vector()).0
+    }
+
+    /// The error code spelled out on a line of its own, for the exceptions whose error code
+    /// means something.
+    fn write_error_code(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.frame.error_code;
+        match self.vector() {
+            // Invalid TSS, segment not present, stack-segment fault, general protection fault.
+            10..=13 => match SelectorErrorCode::new(code) {
+                Some(selector) if !selector.is_null() => {
+                    let external = if selector.external() { ", during an external event" } else { "" };
+                    writeln!(f, "error code {code:#x}: {:?} index {}{external}", selector.descriptor_table(), selector.index())
+                }
+                _ => writeln!(f, "error code {code:#x}: no selector"),
+            },
+            14 => {
+                let flags = PageFaultErrorCode::from_bits_truncate(code);
+                let address = self.address.unwrap_or_default();
+                writeln!(f, "error code {code:#x}: {flags:?} accessing {address:#x}")
+            }
+            21 => {
+                let cause = match code & 0x7FFF {
+                    1 => "near return to a different address",
+                    2 => "far return or iret to a different address",
+                    3 => "indirect branch without endbranch",
+                    4 => "rstorssp",
+                    5 => "setssbsy",
+                    _ => "unknown cause",
+                };
+                writeln!(f, "error code {code:#x}: {cause}")
+            }
+            29 | 30 => writeln!(f, "error code {code:#x}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// ```text
+/// EXCEPTION: GENERAL PROTECTION FAULT (#GP, vector 13)
+/// error code 0x10: Gdt index 2
+/// rip    0x0000000000812a4b  rsp    0x0000010000203f48  rflags 0x0000000000010086
+/// ...
+/// ```
+///
+/// Stack overflows name the stack before the error code:
+///
+/// ```text
+/// EXCEPTION: STACK OVERFLOW (#DF, vector 8)
+/// the boot stack ran into its guard page at 0x000001000000f000
+/// ```
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "EXCEPTION: {} ({}, vector {})", self.name(), self.mnemonic(), self.vector())?;
+        if let Some(stack) = self.overflowed {
+            writeln!(f, "the {} stack ran into its guard page at {:#018x}", stack.name, stack.guard_page().start)?;
+        }
+        self.write_error_code(f)?;
+        write!(f, "{}", self.frame)
+    }
+}
+
+/// Mnemonic and name of exception `vector`.
+fn describe(vector: u8) -> (&'static str, &'static str) {
+    match vector {
+        0 => ("#DE", "DIVIDE ERROR"),
+        1 => ("#DB", "DEBUG"),
+        2 => ("NMI", "NON-MASKABLE INTERRUPT"),
+        3 => ("#BP", "BREAKPOINT"),
+        4 => ("#OF", "OVERFLOW"),
+        5 => ("#BR", "BOUND RANGE EXCEEDED"),
+        6 => ("#UD", "INVALID OPCODE"),
+        7 => ("#NM", "DEVICE NOT AVAILABLE"),
+        8 => ("#DF", "DOUBLE FAULT"),
+        9 => ("CSO", "COPROCESSOR SEGMENT OVERRUN"),
+        10 => ("#TS", "INVALID TSS"),
+        11 => ("#NP", "SEGMENT NOT PRESENT"),
+        12 => ("#SS", "STACK-SEGMENT FAULT"),
+        13 => ("#GP", "GENERAL PROTECTION FAULT"),
+        14 => ("#PF", "PAGE FAULT"),
+        16 => ("#MF", "X87 FLOATING-POINT EXCEPTION"),
+        17 => ("#AC", "ALIGNMENT CHECK"),
+        18 => ("#MC", "MACHINE CHECK"),
+        19 => ("#XM", "SIMD FLOATING-POINT EXCEPTION"),
+        20 => ("#VE", "VIRTUALIZATION EXCEPTION"),
+        21 => ("#CP", "CONTROL PROTECTION EXCEPTION"),
+        28 => ("#HV", "HYPERVISOR INJECTION EXCEPTION"),
+        29 => ("#VC", "VMM COMMUNICATION EXCEPTION"),
+        30 => ("#SX", "SECURITY EXCEPTION"),
+        _ => ("#??", "RESERVED EXCEPTION"),
+    }
+}
+
+trap_entry!(divide_error_entry, 0 => fatal_handler);
+trap_entry!(debug_entry, 1 => debug_handler);
+trap_entry!(non_maskable_interrupt_entry, 2 => fatal_handler);
+trap_entry!(breakpoint_entry, 3 => breakpoint_handler);
+trap_entry!(overflow_entry, 4 => fatal_handler);
+trap_entry!(bound_range_exceeded_entry, 5 => fatal_handler);
+trap_entry!(invalid_opcode_entry, 6 => fatal_handler);
+trap_entry!(device_not_available_entry, 7 => fatal_handler);
+trap_entry!(double_fault_entry, 8, error_code => fatal_handler);
+trap_entry!(coprocessor_segment_overrun_entry, 9 => fatal_handler);
+trap_entry!(invalid_tss_entry, 10, error_code => fatal_handler);
+trap_entry!(segment_not_present_entry, 11, error_code => fatal_handler);
+trap_entry!(stack_segment_fault_entry, 12, error_code => fatal_handler);
+trap_entry!(general_protection_fault_entry, 13, error_code => fatal_handler);
+trap_entry!(page_fault_entry, 14, error_code => fatal_handler);
+trap_entry!(x87_floating_point_entry, 16 => fatal_handler);
+trap_entry!(alignment_check_entry, 17, error_code => fatal_handler);
+trap_entry!(machine_check_entry, 18 => fatal_handler);
+trap_entry!(simd_floating_point_entry, 19 => fatal_handler);
+trap_entry!(virtualization_entry, 20 => fatal_handler);
+trap_entry!(cp_protection_entry, 21, error_code => fatal_handler);
+trap_entry!(hv_injection_entry, 28 => fatal_handler);
+trap_entry!(vmm_communication_entry, 29, error_code => fatal_handler);
+trap_entry!(security_entry, 30, error_code => fatal_handler);
+
+/// Points every exception vector the CPU can raise at its entry. Vectors 15, 22 to 27 and 31 are
+/// reserved and never raised.
+pub(crate) fn install(idt: &mut InterruptDescriptorTable) {
+    // SAFETY: the entries are defined by `trap_entry!` for the vector they are installed at,
+    // with or without an error code as the CPU pushes it. The interrupt stack table slots are
+    // filled in by whoever sets up the TSS, as the constants require.
+    unsafe {
+        set(&mut idt.divide_error, divide_error_entry);
+        set(&mut idt.debug, debug_entry);
+        set(&mut idt.non_maskable_interrupt, non_maskable_interrupt_entry).set_stack_index(NMI_IST_INDEX);
+        set(&mut idt.breakpoint, breakpoint_entry);
+        set(&mut idt.overflow, overflow_entry);
+        set(&mut idt.bound_range_exceeded, bound_range_exceeded_entry);
+        set(&mut idt.invalid_opcode, invalid_opcode_entry);
+        set(&mut idt.device_not_available, device_not_available_entry);
+        set(&mut idt.double_fault, double_fault_entry).set_stack_index(DOUBLE_FAULT_IST_INDEX);
+        set(&mut idt[9], coprocessor_segment_overrun_entry);
+        set(&mut idt.invalid_tss, invalid_tss_entry);
+        set(&mut idt.segment_not_present, segment_not_present_entry);
+        set(&mut idt.stack_segment_fault, stack_segment_fault_entry);
+        set(&mut idt.general_protection_fault, general_protection_fault_entry);
+        set(&mut idt.page_fault, page_fault_entry);
+        set(&mut idt.x87_floating_point, x87_floating_point_entry);
+        set(&mut idt.alignment_check, alignment_check_entry);
+        set(&mut idt.machine_check, machine_check_entry).set_stack_index(MACHINE_CHECK_IST_INDEX);
+        set(&mut idt.simd_floating_point, simd_floating_point_entry);
+        set(&mut idt.virtualization, virtualization_entry);
+        set(&mut idt.cp_protection_exception, cp_protection_entry);
+        set(&mut idt.hv_injection_exception, hv_injection_entry);
+        set(&mut idt.vmm_communication_exception, vmm_communication_entry);
+        set(&mut idt.security_exception, security_entry);
+    }
+}
+
+/// Points `entry` at `handler`, which has to be an interrupt entry point such as the ones made
+/// by `trap_entry!`.
+pub(crate) unsafe fn set<F>(entry: &mut Entry<F>, handler: unsafe extern "C" fn()) -> &mut EntryOptions {
+    unsafe { entry.set_handler_addr(VirtAddr::new(handler as usize as u64)) }
+}
+
+/// Stops in the debugger if one is attached, and otherwise just reports the breakpoint.
+extern "C" fn breakpoint_handler(frame: &mut TrapFrame) {
+    if !gdb::enter(frame, gdb::Stop::Trapped) {
+        warn!("EXCEPTION: BREAKPOINT\n{frame}");
+    }
+}
+
+/// Debug exceptions come from the single steps the debugger asks for.
+extern "C" fn debug_handler(frame: &mut TrapFrame) {
+    if !gdb::enter(frame, gdb::Stop::Trapped) {
+        // Whoever set the trap flag is gone; stop stepping.
+        frame.rflags &= !RFlags::TRAP_FLAG.bits();
+        warn!("EXCEPTION: DEBUG\n{frame}");
+    }
+}
+
+extern "C" fn fatal_handler(frame: &mut TrapFrame) {
+    // Read before anything else can fault and overwrite it.
+    let address = (frame.vector == 14 || frame.vector == 8).then(Cr2::read_raw);
+    crash::set_interrupted(frame);
+    panic!("{}", Exception::new(*frame, address));
+}
@@ -1,27 +1,32 @@
 use bootloader_api::info::MemoryRegionKind::Usable;
 use bootloader_api::info::MemoryRegions;
+use core::ops::Range;
 use x86_64::registers::control::Cr3;
 use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB};
 use x86_64::{PhysAddr, VirtAddr};
 
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryRegions,
+    /// Physical addresses already in use, by the heap, that are never handed out.
+    reserved: Range<u64>,
     next: usize,
 }
 
 impl BootInfoFrameAllocator {
-    pub fn new(memory_map: &'static MemoryRegions) -> Self {
+    pub fn new(memory_map: &'static MemoryRegions, reserved: Range<u64>) -> Self {
         BootInfoFrameAllocator {
             memory_map,
+            reserved,
             next: 0,
         }
     }
-    pub fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+    pub fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> + '_ {
         let regions = self.memory_map.iter();
 
         let usable_regions = regions.filter(|region| region.kind == Usable);
         let address_ranges = usable_regions.map(|region| region.start..region.end);
         let frame_addresses = address_ranges.flat_map(|region| region.step_by(4096));
+        let frame_addresses = frame_addresses.filter(|&address| address + 4096 <= self.reserved.start || address >= self.reserved.end);
 
         frame_addresses.map(|address| PhysFrame::containing_address(PhysAddr::new(address)))
     }
@@ -0,0 +1,528 @@
+// GDB remote serial protocol stub on COM2, which the runner's `--gdb` flag connects to a TCP
+// port. The stub takes over the CPU when GDB connects or sends Ctrl-C, at a breakpoint, and after
+// a single step, and answers GDB's packets with interrupts off until it is told to continue.
+// Memory is reached through the page tables and the bootloader's mapping of physical memory, so
+// a bad address is an error for GDB instead of a page fault, and breakpoints can be written into
+// read-only code.
+
+use log::{debug, info};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::segmentation::{Segment, DS, ES, FS, GS};
+use x86_64::registers::control::Cr3;
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::paging::{OffsetPageTable, PageTable, Translate};
+use x86_64::VirtAddr;
+use crate::serial::{self, Uart};
+use crate::trap::TrapFrame;
+
+/// I/O port of COM2's first register.
+const COM2_BASE: u16 = 0x2F8;
+/// IRQ line COM2 is wired to.
+pub const COM2_IRQ: u8 = 3;
+/// Longest packet taken from or sent to GDB, as announced in `qSupported`.
+const PACKET_SIZE: usize = 4096;
+const MAX_BREAKPOINTS: usize = 32;
+/// The `int3` instruction software breakpoints are made of.
+const INT3: u8 = 0xCC;
+/// GDB asks a running target to stop with a bare Ctrl-C outside any packet.
+const CTRL_C: u8 = 0x03;
+/// Registers of GDB's `i386:x86-64` layout the stub knows: 16 general purpose registers and
+/// rip of 8 bytes each, then eflags and the 6 segment registers of 4 bytes each. GDB treats the
+/// floating point and SSE registers after them as unavailable.
+const REGISTER_COUNT: usize = 24;
+const RIP: usize = 16;
+
+/// Why the kernel stopped, told to GDB as a signal number.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Stop {
+    /// GDB connected or sent Ctrl-C (SIGINT).
+    Interrupted = 2,
+    /// A breakpoint or the end of a single step (SIGTRAP).
+    Trapped = 5,
+}
+
+struct Stub {
+    link: Link,
+    present: bool,
+    /// Set by the first packet from GDB and cleared when it detaches. Breakpoints and debug
+    /// exceptions only stop the kernel while a debugger is attached.
+    attached: bool,
+    last_stop: Stop,
+    memory: Memory,
+    breakpoints: Breakpoints,
+    packet: Buffer,
+    reply: Buffer,
+}
+
+static STUB: Mutex<Stub> = Mutex::new(Stub {
+    link: Link { uart: Uart::new(COM2_BASE), pending: None },
+    present: false,
+    attached: false,
+    last_stop: Stop::Interrupted,
+    memory: Memory { physical_offset: VirtAddr::zero() },
+    breakpoints: Breakpoints([None; MAX_BREAKPOINTS]),
+    packet: Buffer::new(),
+    reply: Buffer::new(),
+});
+
+/// Looks for COM2 and, if it is there, gets it ready for GDB to connect at any time.
+/// `physical_memory_offset` is where the bootloader mapped all of physical memory.
+pub fn init(physical_memory_offset: VirtAddr) {
+    let present = without_interrupts(|| {
+        let mut stub = STUB.lock();
+        stub.memory = Memory { physical_offset: physical_memory_offset };
+        stub.present = stub.link.uart.is_present();
+        if stub.present {
+            stub.link.uart.init();
+            stub.link.uart.set_interrupts(serial::IER_RECEIVED);
+        }
+        stub.present
+    });
+    if present {
+        info!("GDB stub waiting on COM2");
+    } else {
+        debug!("No COM2, GDB stub disabled");
+    }
+}
+
+/// Hands the CPU to an attached debugger, which reads and changes `frame` until it continues.
+/// Returns false, without doing anything, if no debugger is attached.
+pub(crate) fn enter(frame: &mut TrapFrame, stop: Stop) -> bool {
+    // A breakpoint in the stub itself must not deadlock; it is left to the caller.
+    let Some(mut stub) = STUB.try_lock() else { return false };
+    if !stub.attached {
+        return false;
+    }
+    frame.rflags &= !RFlags::TRAP_FLAG.bits();
+    stub.stop(stop);
+    stub.session(frame);
+    true
+}
+
+/// Called by the COM2 interrupt: GDB sent something while the kernel was running, either
+/// Ctrl-C or the first packet of a new connection.
+pub(crate) fn handle_interrupt(frame: &mut TrapFrame) {
+    let Some(mut stub) = STUB.try_lock() else { return };
+    if !stub.present {
+        return;
+    }
+    let Some(byte) = stub.link.uart.try_receive() else { return };
+    if byte == CTRL_C {
+        stub.stop(Stop::Interrupted);
+    } else {
+        // A new connection starts with its own questions rather than waiting for a stop reply.
+        stub.last_stop = Stop::Interrupted;
+        stub.link.pending = Some(byte);
+    }
+    stub.session(frame);
+}
+
+/// What to do after a packet.
+enum Next {
+    Reply,
+    Resume { step: bool },
+    /// Take out the breakpoints and run without the debugger, answering first unless GDB is
+    /// already gone.
+    Detach { reply: bool },
+}
+
+impl Stub {
+    /// Tells GDB the kernel stopped.
+    fn stop(&mut self, stop: Stop) {
+        self.last_stop = stop;
+        self.reply.clear();
+        self.reply.push_stop(stop);
+        self.link.send_packet(self.reply.as_slice());
+    }
+
+    /// Answers GDB's packets until it continues, steps or detaches.
+    fn session(&mut self, frame: &mut TrapFrame) {
+        loop {
+            self.link.receive_packet(&mut self.packet);
+            self.attached = true;
+            self.reply.clear();
+            match self.execute(frame) {
+                Next::Reply => self.link.send_packet(self.reply.as_slice()),
+                Next::Resume { step } => {
+                    if step {
+                        frame.rflags |= RFlags::TRAP_FLAG.bits();
+                    }
+                    return;
+                }
+                Next::Detach { reply } => {
+                    if reply {
+                        self.link.send_packet(self.reply.as_slice());
+                    }
+                    self.breakpoints.remove_all(self.memory);
+                    self.attached = false;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Runs the packet in `self.packet`, leaving the answer in `self.reply`. Unknown packets get
+    /// the empty reply, which tells GDB they are not supported.
+    fn execute(&mut self, frame: &mut TrapFrame) -> Next {
+        let packet = self.packet.as_slice();
+        let reply = &mut self.reply;
+        let Some((&command, arguments)) = packet.split_first() else { return Next::Reply };
+        match command {
+            b'?' => reply.push_stop(self.last_stop),
+            b'g' => {
+                for number in 0..REGISTER_COUNT {
+                    let (size, value) = read_register(frame, number).expect("every register below the count exists");
+                    reply.push_hex(&value.to_le_bytes()[..size]);
+                }
+            }
+            b'G' => {
+                let mut hex = arguments;
+                for number in 0..REGISTER_COUNT {
+                    let size = register_size(number);
+                    let Some(value) = hex.get(..2 * size).and_then(parse_le_hex) else { break };
+                    write_register(frame, number, value);
+                    hex = &hex[2 * size..];
+                }
+                reply.push_str("OK");
+            }
+            b'p' => match parse_hex(arguments).and_then(|number| read_register(frame, number as usize)) {
+                Some((size, value)) => reply.push_hex(&value.to_le_bytes()[..size]),
+                None => reply.push_str("E00"),
+            },
+            b'P' => {
+                let written = split(arguments, b'=').and_then(|(number, value)| {
+                    let number = parse_hex(number).filter(|&number| number < REGISTER_COUNT as u64)?;
+                    write_register(frame, number as usize, parse_le_hex(value)?)
+                });
+                reply.push_str(if written.is_some() { "OK" } else { "E00" });
+            }
+            b'm' => match split(arguments, b',').and_then(|(address, len)| Some((parse_hex(address)?, parse_hex(len)?))) {
+                Some((address, len)) => {
+                    // Two hex digits per byte have to fit in the reply.
+                    let len = len.min(PACKET_SIZE as u64 / 2);
+                    // An unmapped byte ends the reply early; if it is the first, that is an error.
+                    for offset in 0..len {
+                        match self.memory.read(address.wrapping_add(offset)) {
+                            Some(byte) => reply.push_hex(&[byte]),
+                            None if offset == 0 => reply.push_str("E14"),
+                            None => break,
+                        }
+                    }
+                }
+                None => reply.push_str("E00"),
+            },
+            b'M' => {
+                let written = split(arguments, b':').and_then(|(range, data)| {
+                    let (address, len) = split(range, b',')?;
+                    let (address, len) = (parse_hex(address)?, parse_hex(len)?);
+                    if data.len() as u64 != 2 * len {
+                        return None;
+                    }
+                    for (offset, digits) in data.chunks(2).enumerate() {
+                        self.memory.write(address.wrapping_add(offset as u64), parse_hex(digits)? as u8)?;
+                    }
+                    Some(())
+                });
+                reply.push_str(if written.is_some() { "OK" } else { "E14" });
+            }
+            b'c' | b's' => {
+                if let Some(address) = parse_hex(arguments) {
+                    frame.rip = address;
+                }
+                return Next::Resume { step: command == b's' };
+            }
+            b'Z' | b'z' => {
+                // Only software breakpoints, `Z0,address,kind`.
+                let Some(address) = arguments.strip_prefix(b"0,").and_then(|rest| parse_hex(split(rest, b',')?.0)) else {
+                    return Next::Reply;
+                };
+                let done = if command == b'Z' {
+                    self.breakpoints.insert(self.memory, address)
+                } else {
+                    self.breakpoints.remove(self.memory, address)
+                };
+                reply.push_str(if done { "OK" } else { "E14" });
+            }
+            b'D' => {
+                reply.push_str("OK");
+                return Next::Detach { reply: true };
+            }
+            // The kernel cannot be killed; it runs on without the debugger.
+            b'k' => return Next::Detach { reply: false },
+            // There is only one thread to pick.
+            b'H' => reply.push_str("OK"),
+            b'q' if arguments.starts_with(b"Supported") => reply.push_str("PacketSize=1000"),
+            b'q' if arguments == b"Attached" => reply.push_str("1"),
+            _ => {}
+        }
+        Next::Reply
+    }
+}
+
+/// Size in bytes of register `number` in GDB's layout.
+fn register_size(number: usize) -> usize {
+    if number <= RIP { 8 } else { 4 }
+}
+
+/// The registers kept in the frame, in GDB's numbering.
+fn frame_register(frame: &mut TrapFrame, number: usize) -> Option<&mut u64> {
+    Some(match number {
+        0 => &mut frame.rax,
+        1 => &mut frame.rbx,
+        2 => &mut frame.rcx,
+        3 => &mut frame.rdx,
+        4 => &mut frame.rsi,
+        5 => &mut frame.rdi,
+        6 => &mut frame.rbp,
+        7 => &mut frame.rsp,
+        8 => &mut frame.r8,
+        9 => &mut frame.r9,
+        10 => &mut frame.r10,
+        11 => &mut frame.r11,
+        12 => &mut frame.r12,
+        13 => &mut frame.r13,
+        14 => &mut frame.r14,
+        15 => &mut frame.r15,
+        RIP => &mut frame.rip,
+        17 => &mut frame.rflags,
+        18 => &mut frame.cs,
+        19 => &mut frame.ss,
+        _ => return None,
+    })
+}
+
+/// Size and value of register `number`. The data segment registers are not saved on entry, but
+/// the stub does not change them, so they are read as they are.
+fn read_register(frame: &mut TrapFrame, number: usize) -> Option<(usize, u64)> {
+    let value = match number {
+        20 => DS::get_reg().0.into(),
+        21 => ES::get_reg().0.into(),
+        22 => FS::get_reg().0.into(),
+        23 => GS::get_reg().0.into(),
+        _ => *frame_register(frame, number)?,
+    };
+    Some((register_size(number), value))
+}
+
+/// Changes register `number` for when the kernel resumes. Writes to segment registers are
+/// ignored: the kernel only runs with the bootloader's and the GDT's selectors.
+fn write_register(frame: &mut TrapFrame, number: usize, value: u64) -> Option<()> {
+    if number <= 17 {
+        *frame_register(frame, number)? = value;
+    }
+    Some(())
+}
+
+/// Memory of the stopped kernel, looked up in the active page tables.
+#[derive(Clone, Copy)]
+struct Memory {
+    physical_offset: VirtAddr,
+}
+
+impl Memory {
+    /// Where the byte at `address` is in the mapping of physical memory, which is writable even
+    /// where `address` itself is mapped read-only.
+    fn locate(self, address: u64) -> Option<*mut u8> {
+        let address = VirtAddr::try_new(address).ok()?;
+        let level_4_table = self.physical_offset + Cr3::read().0.start_address().as_u64();
+        // SAFETY: like all page tables, the level 4 table is in physical memory mapped at
+        // `physical_offset`. The translation only reads it, and the kernel is stopped.
+        let table = unsafe { OffsetPageTable::new(&mut *level_4_table.as_mut_ptr::<PageTable>(), self.physical_offset) };
+        let physical = table.translate_addr(address)?;
+        Some((self.physical_offset + physical.as_u64()).as_mut_ptr())
+    }
+
+    fn read(self, address: u64) -> Option<u8> {
+        // SAFETY: the byte is mapped, as the translation just found.
+        self.locate(address).map(|byte| unsafe { byte.read_volatile() })
+    }
+
+    fn write(self, address: u64, value: u8) -> Option<()> {
+        // SAFETY: the byte is mapped; what the debugger writes there is up to the debugger.
+        self.locate(address).map(|byte| unsafe { byte.write_volatile(value) })
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    address: u64,
+    /// The byte the `int3` replaced.
+    original: u8,
+}
+
+struct Breakpoints([Option<Breakpoint>; MAX_BREAKPOINTS]);
+
+impl Breakpoints {
+    /// Puts an `int3` at `address`. Returns false if the address is not mapped or all
+    /// breakpoints are in use.
+    fn insert(&mut self, memory: Memory, address: u64) -> bool {
+        if self.0.iter().flatten().any(|breakpoint| breakpoint.address == address) {
+            return true;
+        }
+        let Some(slot) = self.0.iter_mut().find(|slot| slot.is_none()) else { return false };
+        let Some(original) = memory.read(address) else { return false };
+        if memory.write(address, INT3).is_none() {
+            return false;
+        }
+        *slot = Some(Breakpoint { address, original });
+        true
+    }
+
+    /// Puts back the byte under the breakpoint at `address`.
+    fn remove(&mut self, memory: Memory, address: u64) -> bool {
+        let Some(slot) = self.0.iter_mut().find(|slot| slot.is_some_and(|breakpoint| breakpoint.address == address)) else {
+            return false;
+        };
+        if let Some(breakpoint) = slot.take() {
+            memory.write(address, breakpoint.original);
+        }
+        true
+    }
+
+    fn remove_all(&mut self, memory: Memory) {
+        for breakpoint in self.0.iter_mut().filter_map(Option::take) {
+            memory.write(breakpoint.address, breakpoint.original);
+        }
+    }
+}
+
+/// The wire to GDB: `$data#checksum` packets, each acknowledged with `+`, or `-` to have it sent
+/// again.
+struct Link {
+    uart: Uart,
+    /// A byte the interrupt handler already took from the UART.
+    pending: Option<u8>,
+}
+
+impl Link {
+    fn read(&mut self) -> u8 {
+        self.pending.take().unwrap_or_else(|| self.uart.receive_polled())
+    }
+
+    /// Waits for the next intact packet, acknowledges it and leaves its data in `packet`.
+    fn receive_packet(&mut self, packet: &mut Buffer) {
+        loop {
+            while self.read() != b'$' {}
+            packet.clear();
+            let mut checksum = 0_u8;
+            loop {
+                match self.read() {
+                    b'#' => break,
+                    // GDB gave up on the packet and started again.
+                    b'$' => {
+                        packet.clear();
+                        checksum = 0;
+                    }
+                    byte => {
+                        checksum = checksum.wrapping_add(byte);
+                        packet.push(byte);
+                    }
+                }
+            }
+            let sent = [self.read(), self.read()];
+            if !packet.overflowed && parse_hex(&sent) == Some(checksum.into()) {
+                self.uart.send(b'+');
+                return;
+            }
+            self.uart.send(b'-');
+        }
+    }
+
+    /// Sends `data` as a packet until GDB acknowledges it.
+    fn send_packet(&mut self, data: &[u8]) {
+        let checksum = data.iter().fold(0_u8, |sum, &byte| sum.wrapping_add(byte));
+        loop {
+            self.uart.send(b'$');
+            for &byte in data {
+                self.uart.send(byte);
+            }
+            self.uart.send(b'#');
+            self.uart.send(hex_digit(checksum >> 4));
+            self.uart.send(hex_digit(checksum & 0xF));
+            loop {
+                match self.read() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Packet contents. Bytes past [PACKET_SIZE] are dropped and the buffer marked as overflowed.
+struct Buffer {
+    bytes: [u8; PACKET_SIZE],
+    len: usize,
+    overflowed: bool,
+}
+
+impl Buffer {
+    const fn new() -> Self {
+        Self { bytes: [0; PACKET_SIZE], len: 0, overflowed: false }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.overflowed = false;
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == PACKET_SIZE {
+            self.overflowed = true;
+            return;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+    }
+
+    fn push_str(&mut self, s: &str) {
+        for &byte in s.as_bytes() {
+            self.push(byte);
+        }
+    }
+
+    /// Two hex digits per byte, in order.
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(hex_digit(byte >> 4));
+            self.push(hex_digit(byte & 0xF));
+        }
+    }
+
+    /// `Snn`, the stop reply.
+    fn push_stop(&mut self, stop: Stop) {
+        self.push(b'S');
+        self.push_hex(&[stop as u8]);
+    }
+}
+
+fn hex_digit(value: u8) -> u8 {
+    b"0123456789abcdef"[value as usize & 0xF]
+}
+
+/// A big-endian hex number, as GDB writes addresses and lengths.
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0, |value, &digit| Some(value << 4 | (digit as char).to_digit(16)? as u64))
+}
+
+/// Hex bytes of a little-endian number, as GDB writes register values.
+fn parse_le_hex(digits: &[u8]) -> Option<u64> {
+    if digits.len() % 2 != 0 || digits.len() > 16 {
+        return None;
+    }
+    digits.chunks(2).rev().try_fold(0, |value, byte| Some(value << 8 | parse_hex(byte)?))
+}
+
+fn split(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let at = bytes.iter().position(|&byte| byte == separator)?;
+    Some((&bytes[..at], &bytes[at + 1..]))
+}
@@ -1,32 +1,92 @@
-use core::ptr::addr_of;
-use lazy_static::lazy_static;
+// The GDT and the TSS, whose interrupt stack table holds the stacks the exceptions in
+// `kernel::exceptions` switch to. Each of those stacks is mapped from fresh frames with an
+// unmapped guard page below it, and registered along with the bootloader's stack, so an overflow
+// of any of them is reported by name instead of faulting again.
+
+use core::arch::asm;
+use spin::Once;
+use kernel::exceptions::{self, Stack, DOUBLE_FAULT_IST_INDEX, MACHINE_CHECK_IST_INDEX, NMI_IST_INDEX};
+use log::debug;
 use x86_64::instructions::segmentation::{Segment, CS, DS, ES, FS, GS, SS};
 use x86_64::instructions::tables::load_tss;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTableFlags, PageTableIndex, Size4KiB, Translate};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
+use crate::BOOTLOADER_CONFIG;
 
-pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// Pages in each interrupt stack. A fatal exception panics on it, and drawing the panic screen
+/// needs a good deal more than the handler itself.
+const INTERRUPT_STACK_PAGES: u64 = 16;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+/// The interrupt stacks, by the slot they go in.
+const INTERRUPT_STACKS: [(u16, &str); 3] = [
+    (DOUBLE_FAULT_IST_INDEX, "double fault"),
+    (NMI_IST_INDEX, "NMI"),
+    (MACHINE_CHECK_IST_INDEX, "machine check"),
+];
+
+static TSS: Once<TaskStateSegment> = Once::new();
+static GDT: Once<(GlobalDescriptorTable, Selectors)> = Once::new();
+
+struct Selectors {
+    code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+    tss_selector: SegmentSelector,
+}
+
+/// The stack the bootloader started the kernel on, which the kernel still runs on. The
+/// bootloader maps `kernel_stack_size` bytes of it with nothing mapped right above or below, so
+/// its top is the end of the mapped pages the stack pointer is in.
+fn boot_stack(mapper: &OffsetPageTable) -> Stack {
+    let rsp: u64;
+    // SAFETY: only copies rsp.
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags)) };
+
+    let size = BOOTLOADER_CONFIG.kernel_stack_size;
+    let mut top = Page::<Size4KiB>::containing_address(VirtAddr::new(rsp)) + 1;
+    // The stack pointer is in the stack, so the top is less than the stack's size above it.
+    while top.start_address().as_u64() - rsp < size && mapper.translate_addr(top.start_address()).is_some() {
+        top += 1;
+    }
+    let top = top.start_address();
+    let bottom = (top - size).align_down(Size4KiB::SIZE);
+    assert!(
+        mapper.translate_addr(top).is_none(),
+        "no unmapped page within {size:#x} bytes above the stack pointer {rsp:#x} to end the boot stack"
+    );
+    assert!(
+        mapper.translate_addr(bottom - 1u64).is_none(),
+        "the page below the boot stack at {bottom:#x}..{top:#x} is mapped, so it is no guard page"
+    );
+    Stack { name: "boot", bottom, top }
+}
 
-            let stack_start = VirtAddr::from_ptr( addr_of!(STACK) );
-            stack_start + STACK_SIZE as u64 // stack_end
-        };
+/// Maps the interrupt stacks, then loads the GDT and the TSS.
+pub fn init(mapper: &mut OffsetPageTable, frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    let boot_stack = boot_stack(mapper);
+    debug!("boot stack at {:#x}..{:#x}", boot_stack.bottom, boot_stack.top);
+    exceptions::add_stack(boot_stack);
+
+    let tss = TSS.call_once(|| {
+        let mut tss = TaskStateSegment::new();
+        let mut next = free_region(mapper);
+        for (index, name) in INTERRUPT_STACKS {
+            let stack = map_stack(name, next, mapper, frame_allocator);
+            debug!("{name} stack at {:#x}..{:#x}", stack.bottom, stack.top);
+            exceptions::add_stack(stack);
+            tss.interrupt_stack_table[index as usize] = stack.top;
+            next = Page::containing_address(stack.top);
+        }
         tss
-    };
+    });
 
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+    let (gdt, selectors) = GDT.call_once(|| {
         let mut gdt: GlobalDescriptorTable = GlobalDescriptorTable::new();
 
         let code_selector = gdt.append(Descriptor::kernel_code_segment());
         let data_selector = gdt.append(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
+        let tss_selector = gdt.append(Descriptor::tss_segment(tss));
 
         (
             gdt,
@@ -36,25 +96,46 @@ lazy_static! {
                 tss_selector,
             },
         )
-    };
+    });
+
+    gdt.load();
+    unsafe {
+        CS::set_reg(selectors.code_selector);
+        SS::set_reg(selectors.data_selector);
+        DS::set_reg(selectors.data_selector);
+        ES::set_reg(selectors.data_selector);
+        FS::set_reg(selectors.data_selector);
+        GS::set_reg(selectors.data_selector);
+
+        load_tss(selectors.tss_selector)
+    }
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    data_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+/// First page of a 512 GiB stretch of the higher half that nothing is mapped in, the last one
+/// the bootloader left free.
+fn free_region(mapper: &OffsetPageTable) -> Page {
+    let level_4_table = mapper.level_4_table();
+    let index = (256..512)
+        .rev()
+        .find(|&index| level_4_table[index].is_unused())
+        .expect("no free level 4 entry for the interrupt stacks");
+    let zero = PageTableIndex::new(0);
+    Page::from_page_table_indices(PageTableIndex::new(index as u16), zero, zero, zero)
 }
 
-pub fn init() {
-    GDT.0.load();
-    unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        SS::set_reg(GDT.1.data_selector);
-        DS::set_reg(GDT.1.data_selector);
-        ES::set_reg(GDT.1.data_selector);
-        FS::set_reg(GDT.1.data_selector);
-        GS::set_reg(GDT.1.data_selector);
-
-        load_tss(GDT.1.tss_selector)
+/// Maps a stack of [INTERRUPT_STACK_PAGES] fresh frames above `guard`, which stays unmapped.
+fn map_stack(name: &'static str, guard: Page, mapper: &mut OffsetPageTable, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Stack {
+    let bottom = guard + 1;
+    let top = bottom + INTERRUPT_STACK_PAGES;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    for page in Page::range(bottom, top) {
+        let frame = frame_allocator.allocate_frame().expect("out of frames for the interrupt stacks");
+        unsafe {
+            mapper
+                .map_to(page, frame, flags, frame_allocator)
+                .expect("interrupt stack mapping failed")
+                .flush();
+        }
     }
-}
\ No newline at end of file
+    Stack { name, bottom: bottom.start_address(), top: top.start_address() }
+}
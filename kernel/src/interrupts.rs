@@ -1,15 +1,17 @@
-use core::fmt::Write;
+use core::hint::spin_loop;
 use core::ptr::NonNull;
-use crate::serial;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use log::{debug, info};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use x86_64::{PhysAddr, VirtAddr};
-use crate::HandlerTable;
+use crate::{exceptions, gdb, serial, time, HandlerTable};
+use crate::trap::{trap_entry, TrapFrame};
 use acpi::{AcpiHandler, AcpiTables, PhysicalMapping};
 use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
-use x86_64::registers::control::Cr2;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 use x86_64::structures::paging::{FrameAllocator, Mapper, PhysFrame, Size4KiB};
+use x86_64::instructions::interrupts::without_interrupts;
 use x86_64::instructions::port::Port;
 // This code is largely Copyright (c) 2019 Philipp Oppermann.
 // Gabriel Ferrer added:
@@ -111,6 +113,33 @@ pub enum APICOffset {
     R0x3F0 = 0x3F0,   // RESERVED = 0x3F0
 }
 
+impl APICOffset {
+    /// Every register that can be read, in address order. Leaves out the reserved slots and EOI,
+    /// which is write-only.
+    pub const READABLE: [APICOffset; 45] = {
+        use APICOffset::*;
+        [
+            Ir, Vr, Tpr, Apr, Ppr, Rrd, Ldr, Dfr, Svr,
+            Isr1, Isr2, Isr3, Isr4, Isr5, Isr6, Isr7, Isr8,
+            Tmr1, Tmr2, Tmr3, Tmr4, Tmr5, Tmr6, Tmr7, Tmr8,
+            Irr1, Irr2, Irr3, Irr4, Irr5, Irr6, Irr7, Irr8,
+            Esr, LvtCmci, Icr1, Icr2, LvtT, LvtTsr, LvtPmcr, LvtLint0, LvtLint1, LvtE, Ticr, Tccr,
+        ]
+    };
+}
+
+/// Reads a local APIC register. Returns 0 before the APIC is set up.
+pub fn read_lapic(register: APICOffset) -> u32 {
+    // Every interrupt handler takes the lock to signal the end of its interrupt.
+    without_interrupts(|| {
+        let lapic = LAPIC_ADDR.lock();
+        if lapic.address.is_null() {
+            return 0;
+        }
+        unsafe { lapic.address.offset(register as isize / 4).read_volatile() }
+    })
+}
+
 pub struct AcpiHandlerImpl {
     physical_memory_offset: VirtAddr,
 }
@@ -163,12 +192,13 @@ lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
 
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        idt.double_fault.set_handler_fn(double_fault_handler);
+        exceptions::install(&mut idt);
 
         idt[InterruptIndex::Timer as u8].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard as u8].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Serial as u8].set_handler_fn(serial_interrupt_handler);
+        // SAFETY: the entry is defined by `trap_entry!` for this vector.
+        unsafe { exceptions::set(&mut idt[InterruptIndex::Debugger as u8], debugger_interrupt_entry) };
 
         idt
     };
@@ -189,6 +219,22 @@ unsafe fn init_io_apic(
         ioapic_pointer
             .offset(4)
             .write_volatile(InterruptIndex::Keyboard as u8 as u32);
+        route_irq(ioapic_pointer, serial::COM1_IRQ, InterruptIndex::Serial);
+        route_irq(ioapic_pointer, gdb::COM2_IRQ, InterruptIndex::Debugger);
+    }
+}
+
+/// Points the IO APIC redirection entry for ISA `irq` at `vector` on the bootstrap processor,
+/// edge triggered and unmasked.
+unsafe fn route_irq(ioapic_pointer: *mut u32, irq: u8, vector: InterruptIndex) {
+    // IOREGSEL at offset 0 picks a register, IOWIN at offset 0x10 reads or writes it. Each
+    // redirection entry is two registers starting at 0x10.
+    let entry = 0x10 + 2 * irq as u32;
+    unsafe {
+        ioapic_pointer.offset(0).write_volatile(entry);
+        ioapic_pointer.offset(4).write_volatile(vector as u8 as u32);
+        ioapic_pointer.offset(0).write_volatile(entry + 1);
+        ioapic_pointer.offset(4).write_volatile(0); // Destination: local APIC 0
     }
 }
 
@@ -205,25 +251,107 @@ unsafe fn init_local_apic(
         init_timer(lapic_pointer);
         init_keyboard(lapic_pointer);
     }
-    writeln!(serial(), "init LAPIC_ADDR {:?}", LAPIC_ADDR.lock()).unwrap();
+    debug!("init LAPIC_ADDR {:?}", LAPIC_ADDR.lock());
 }
 
+/// Input clock of the PIT, in Hz.
+const PIT_FREQUENCY: u32 = 1_193_182;
+/// Length of the PIT one-shot the LAPIC timer is measured against.
+const CALIBRATION_MS: u32 = 10;
+/// Timer interrupt rate used until [set_timer_hz] is called.
+pub const DEFAULT_TIMER_HZ: u32 = 60;
+
+/// LAPIC timer counts per second, after the divider. Measured once at boot.
+static LAPIC_TIMER_FREQUENCY: AtomicU32 = AtomicU32::new(0);
+static TIMER_HZ: AtomicU32 = AtomicU32::new(0);
+
 unsafe fn init_timer(lapic_pointer: *mut u32) {
     unsafe {
         let svr = lapic_pointer.offset(APICOffset::Svr as isize / 4);
         svr.write_volatile(svr.read_volatile() | 0x100); // Set bit 8
 
-        let lvt_lint1 = lapic_pointer.offset(APICOffset::LvtT as isize / 4);
-        lvt_lint1.write_volatile(0x20 | (1 << 17)); // Vector 0x20, periodic mode
-
         let tdcr = lapic_pointer.offset(APICOffset::Tdcr as isize / 4);
-        tdcr.write_volatile(0x2); // Divide by 16 mode
+        tdcr.write_volatile(0x3); // Divide by 16 mode
+
+        let frequency = calibrate_timer(lapic_pointer);
+        LAPIC_TIMER_FREQUENCY.store(frequency, Ordering::Relaxed);
+        info!("LAPIC timer calibrated at {} Hz, TSC at {} Hz", frequency, time::tsc_frequency());
+
+        let lvt_timer = lapic_pointer.offset(APICOffset::LvtT as isize / 4);
+        lvt_timer.write_volatile(0x20 | (1 << 17)); // Vector 0x20, periodic mode
 
+        set_timer_hz(DEFAULT_TIMER_HZ);
+    }
+}
+
+/// Counts how far the LAPIC timer gets during a `CALIBRATION_MS` one-shot of PIT channel 2
+/// and returns the timer frequency in Hz. Channel 2 is used because its output can be polled
+/// through port 0x61 without taking an interrupt. The TSC is measured over the same interval
+/// to start the monotonic clock in [time].
+unsafe fn calibrate_timer(lapic_pointer: *mut u32) -> u32 {
+    let mut gate = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel2 = Port::<u8>::new(0x42);
+    let pit_count = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+
+    unsafe {
+        let lvt_timer = lapic_pointer.offset(APICOffset::LvtT as isize / 4);
+        lvt_timer.write_volatile(1 << 16); // Masked, one-shot mode
         let ticr = lapic_pointer.offset(APICOffset::Ticr as isize / 4);
-        ticr.write_volatile(0x30_0000); // An arbitrary value for the initial value of the timer
+        let tccr = lapic_pointer.offset(APICOffset::Tccr as isize / 4);
+
+        // Gate channel 2 on, keep the speaker off
+        let control = gate.read();
+        gate.write((control & !0x02) | 0x01);
+
+        command.write(0b1011_0000); // Channel 2, low/high byte access, mode 0 (one-shot)
+        channel2.write(pit_count as u8);
+        channel2.write((pit_count >> 8) as u8);
+        ticr.write_volatile(u32::MAX);
+        let tsc_start = time::read_tsc();
+
+        // OUT2 (bit 5) goes high once the PIT count reaches zero
+        while gate.read() & 0x20 == 0 {
+            spin_loop();
+        }
+        let elapsed = u32::MAX - tccr.read_volatile();
+        let tsc_elapsed = time::read_tsc() - tsc_start;
+        time::set_tsc_frequency(tsc_elapsed * (1000 / CALIBRATION_MS) as u64, tsc_start);
+
+        ticr.write_volatile(0);
+        gate.write(control);
+
+        elapsed * (1000 / CALIBRATION_MS)
     }
 }
 
+/// Programs the LAPIC timer to interrupt `hz` times per second. Does nothing before the APIC is
+/// set up.
+pub fn set_timer_hz(hz: u32) {
+    let initial_count = (LAPIC_TIMER_FREQUENCY.load(Ordering::Relaxed) / hz.max(1)).max(1);
+    without_interrupts(|| {
+        let lapic = LAPIC_ADDR.lock();
+        if lapic.address.is_null() {
+            return;
+        }
+        unsafe {
+            let ticr = lapic.address.offset(APICOffset::Ticr as isize / 4);
+            ticr.write_volatile(initial_count);
+        }
+        TIMER_HZ.store(hz, Ordering::Relaxed);
+    });
+}
+
+/// Number of timer interrupts per second.
+pub fn timer_hz() -> u32 {
+    TIMER_HZ.load(Ordering::Relaxed)
+}
+
+/// Frequency of the LAPIC timer after its divider, as measured at boot.
+pub fn lapic_timer_frequency() -> u32 {
+    LAPIC_TIMER_FREQUENCY.load(Ordering::Relaxed)
+}
+
 unsafe fn init_keyboard(lapic_pointer: *mut u32) {
     unsafe {
         let keyboard_register = lapic_pointer.offset(APICOffset::LvtLint1 as isize / 4);
@@ -275,8 +403,8 @@ pub fn init_apic(rsdp: usize, offset: u64, mapper: &mut impl Mapper<Size4KiB>, f
 
     disable_pic();
 
-    writeln!(serial(), "APIC setup completed, pending interrupt and setup IDT.").unwrap();
-    writeln!(serial(), "LAPIC address: {:?}", LAPIC_ADDR.lock()).unwrap();
+    info!("APIC setup completed, pending interrupt and setup IDT.");
+    debug!("LAPIC address: {:?}", LAPIC_ADDR.lock());
     LAPIC_ADDR.lock().address
 }
 
@@ -297,27 +425,21 @@ fn end_interrupt() {
 /// Initializes the interrupt table with the given interrupt handlers.
 pub fn init_idt(handlers: HandlerTable, lapic_pointer: *mut u32) {
     LAPIC_ADDR.lock().address = lapic_pointer;
-    writeln!(serial(), "initialize IDT with LAPIC_ADDR {:?}", LAPIC_ADDR.lock()).unwrap();
+    debug!("initialize IDT with LAPIC_ADDR {:?}", LAPIC_ADDR.lock());
     *(HANDLERS.lock()) = Some(handlers);
 
     IDT.load();
+    serial::enable_interrupts();
     x86_64::instructions::interrupts::enable();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(
-    stack_frame: InterruptStackFrame)
-{
-    writeln!(serial(), "EXCEPTION: BREAKPOINT\n{:#?}", stack_frame).unwrap();
-}
+trap_entry!(debugger_interrupt_entry, InterruptIndex::Debugger as u8 => debugger_interrupt_handler);
 
-extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
-    panic!("EXCEPTION: PAGE FAULT access address: {:?}\n ErrorCode: {:?}\n{:#?}", Cr2::read(), error_code, stack_frame);
-}
-
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame, _error_code: u64) -> !
-{
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+/// GDB sent something on COM2, so it takes over until it continues.
+extern "C" fn debugger_interrupt_handler(frame: &mut TrapFrame) {
+    InterruptIndex::Debugger.count().fetch_add(1, Ordering::Relaxed);
+    gdb::handle_interrupt(frame);
+    end_interrupt();
 }
 
 const PIC_1_OFFSET: u8 = 0x20;
@@ -326,10 +448,41 @@ const PIC_1_OFFSET: u8 = 0x20;
 enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Debugger = PIC_1_OFFSET + gdb::COM2_IRQ,
+    Serial = PIC_1_OFFSET + serial::COM1_IRQ,
+}
+
+impl InterruptIndex {
+    const ALL: [InterruptIndex; 4] = [InterruptIndex::Timer, InterruptIndex::Keyboard, InterruptIndex::Debugger, InterruptIndex::Serial];
+
+    fn name(self) -> &'static str {
+        match self {
+            InterruptIndex::Timer => "timer",
+            InterruptIndex::Keyboard => "keyboard",
+            InterruptIndex::Debugger => "gdb",
+            InterruptIndex::Serial => "serial",
+        }
+    }
+
+    fn count(self) -> &'static AtomicU64 {
+        &INTERRUPT_COUNTS[self as usize - PIC_1_OFFSET as usize]
+    }
+}
+
+/// Interrupts taken per hardware vector, indexed from [PIC_1_OFFSET].
+static INTERRUPT_COUNTS: [AtomicU64; 16] = [const { AtomicU64::new(0) }; 16];
+
+/// Vector, name and number of interrupts taken so far for every hardware interrupt handled.
+pub fn interrupt_counts() -> impl Iterator<Item = (u8, &'static str, u64)> {
+    InterruptIndex::ALL
+        .into_iter()
+        .map(|index| (index as u8, index.name(), index.count().load(Ordering::Relaxed)))
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    
+    InterruptIndex::Timer.count().fetch_add(1, Ordering::Relaxed);
+    time::record_timer_tick();
+
     let h = &*HANDLERS.lock();
     if let Some(handler) = h {
         handler.handle_timer();
@@ -339,6 +492,7 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    InterruptIndex::Keyboard.count().fetch_add(1, Ordering::Relaxed);
 
     lazy_static! {
         static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
@@ -351,12 +505,24 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
 
     let scancode: u8 = unsafe { port.read() };
     if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            let h = &*HANDLERS.lock();
-            if let Some(handler) = h {
+        let h = &*HANDLERS.lock();
+        if let Some(handler) = h {
+            handler.handle_key_event(&key_event);
+            if let Some(key) = keyboard.process_keyevent(key_event) {
                 handler.handle_keyboard(key);
             }
         }
     }
     end_interrupt();
-}
\ No newline at end of file
+}
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    InterruptIndex::Serial.count().fetch_add(1, Ordering::Relaxed);
+    serial::handle_interrupt();
+
+    let h = &*HANDLERS.lock();
+    if let Some(handler) = h {
+        handler.handle_serial();
+    }
+
+    end_interrupt();
+}
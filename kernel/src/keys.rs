@@ -0,0 +1,35 @@
+// Table of keys currently held down, built from the raw make/break codes of the keyboard rather
+// than from the typematic repeat stream, so that several keys can be held at the same time.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use pc_keyboard::{KeyCode, KeyEvent, KeyState};
+
+static KEYS_DOWN: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+fn slot(code: KeyCode) -> (&'static AtomicU64, u64) {
+    let index = code as u8 as usize;
+    (&KEYS_DOWN[index / 64], 1 << (index % 64))
+}
+
+/// Returns true while `code` is held down.
+pub fn is_down(code: KeyCode) -> bool {
+    let (word, bit) = slot(code);
+    word.load(Ordering::Relaxed) & bit != 0
+}
+
+/// Records a key event. Returns the new state if the key went up or down, or `None` for the
+/// repeats sent while a key is held and for single-shot events that change nothing.
+pub(crate) fn record(event: &KeyEvent) -> Option<KeyState> {
+    let (word, bit) = slot(event.code);
+    match event.state {
+        KeyState::Down => {
+            let was_down = word.fetch_or(bit, Ordering::Relaxed) & bit != 0;
+            (!was_down).then_some(KeyState::Down)
+        }
+        KeyState::Up => {
+            let was_down = word.fetch_and(!bit, Ordering::Relaxed) & bit != 0;
+            was_down.then_some(KeyState::Up)
+        }
+        KeyState::SingleShot => None,
+    }
+}
@@ -4,19 +4,21 @@
 
 use core::cell::UnsafeCell;
 use core::panic::PanicInfo;
-use core::fmt::Write;
-use uart_16550::SerialPort;
-use pc_keyboard::DecodedKey;
+use pc_keyboard::{DecodedKey, KeyCode, KeyEvent, KeyState};
 
-mod interrupts;
+pub mod crash;
+pub mod exceptions;
+pub mod gdb;
+pub mod interrupts;
+pub mod logger;
+pub mod serial;
+pub mod keys;
+pub mod time;
+pub mod trap;
 
-extern crate alloc;
+pub use keys::is_down;
 
-pub fn serial() -> SerialPort {
-    let mut port = unsafe { SerialPort::new(0x3F8) };
-    port.init();
-    port
-}
+extern crate alloc;
 
 /// Table of interrupt handlers. This struct uses the
 /// [Builder pattern](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
@@ -24,10 +26,15 @@ pub fn serial() -> SerialPort {
 /// up the handlers. When ready, call the **.start()** method to start up your pluggable
 /// interrupt operating system.
 ///
-/// For now, it only includes timer and keyboard handlers.
+/// For now, it only includes timer, keyboard and serial port handlers. Keys that are held down can also be
+/// polled at any time with [is_down].
 pub struct HandlerTable {
     timer: Option<fn()>,
     keyboard: Option<fn(DecodedKey)>,
+    serial: Option<fn(u8)>,
+    key_pressed: Option<fn(KeyCode)>,
+    key_released: Option<fn(KeyCode)>,
+    timer_hz: Option<u32>,
     startup: Option<fn()>,
     cpu_loop: fn() -> !,
 }
@@ -35,13 +42,17 @@ pub struct HandlerTable {
 impl HandlerTable {
     /// Creates a new HandlerTable with no handlers.
     pub fn new() -> Self {
-        HandlerTable {timer: None, keyboard: None, startup: None, cpu_loop: hlt_loop}
+        HandlerTable {timer: None, keyboard: None, serial: None, key_pressed: None, key_released: None, timer_hz: None, startup: None, cpu_loop: hlt_loop}
     }
 
     /// Starts up a simple operating system using the specified handlers.
     pub fn start(self, lapic_ptr: *mut u32) -> ! {
         self.startup.map(|f| f());
         let fore = self.cpu_loop;
+
+        if let Some(hz) = self.timer_hz {
+            interrupts::set_timer_hz(hz);
+        }
         
         interrupts::init_idt(self, lapic_ptr);
         
@@ -55,6 +66,16 @@ impl HandlerTable {
         self
     }
 
+    /// Sets how many times per second the timer handler runs. The local APIC timer is
+    /// calibrated against the PIT at boot, so the rate is the same on every machine. Without
+    /// this, the timer runs at [interrupts::DEFAULT_TIMER_HZ].
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn timer_hz(mut self, hz: u32) -> Self {
+        self.timer_hz = Some(hz);
+        self
+    }
+
     /// Called by the low-level interrupt routines to handle a timer event.
     pub fn handle_timer(&self) {
         if let Some(timer) = self.timer {
@@ -78,6 +99,58 @@ impl HandlerTable {
         }
     }
 
+    /// Sets the serial port handler, called with each byte received on COM1.
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn serial(mut self, serial_handler: fn(u8)) -> Self {
+        self.serial = Some(serial_handler);
+        self
+    }
+
+    /// Called by the low-level interrupt routines after bytes arrive on the serial port. Without
+    /// a handler they stay buffered for [serial::read_byte].
+    pub fn handle_serial(&self) {
+        if let Some(serial) = self.serial {
+            while let Some(byte) = serial::read_byte() {
+                (serial)(byte)
+            }
+        }
+    }
+
+    /// Sets the key pressed handler. It is called once when a key goes down, not for the
+    /// repeats the keyboard sends while the key is held.
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn key_pressed(mut self, key_pressed_handler: fn(KeyCode)) -> Self {
+        self.key_pressed = Some(key_pressed_handler);
+        self
+    }
+
+    /// Sets the key released handler.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn key_released(mut self, key_released_handler: fn(KeyCode)) -> Self {
+        self.key_released = Some(key_released_handler);
+        self
+    }
+
+    /// Called by the low-level interrupt routines for every raw make or break code, before it
+    /// is decoded. Updates the table behind [is_down] and calls the pressed/released handlers.
+    pub fn handle_key_event(&self, event: &KeyEvent) {
+        match keys::record(event) {
+            Some(KeyState::Down) => {
+                if let Some(key_pressed) = self.key_pressed {
+                    (key_pressed)(event.code)
+                }
+            }
+            Some(KeyState::Up) => {
+                if let Some(key_released) = self.key_released {
+                    (key_released)(event.code)
+                }
+            }
+            _ => {}
+        }
+    }
+
     /// Sets the startup handler.
     /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
     pub fn startup(mut self, startup_handler: fn()) -> Self {
@@ -102,7 +175,8 @@ pub fn hlt_loop() -> ! {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    let _ = writeln!(serial(), "PANIC: {info}");
+    x86_64::instructions::interrupts::disable();
+    crash::report(info);
     hlt_loop();
 }
 
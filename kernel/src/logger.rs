@@ -0,0 +1,284 @@
+// Kernel logger behind the `log` crate's macros. Each record is formatted once, with the time
+// since boot and its target, then handed to the serial port, to a ring buffer of recent lines and
+// to any sinks added with [add_sink]. All of it runs with interrupts disabled, so interrupt
+// handlers can log too.
+
+use core::fmt::{self, Write};
+use log::{Log, Metadata, Record};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::{serial, time};
+
+pub use log::{Level, LevelFilter};
+
+/// Receives every logged line, without a trailing newline.
+pub type Sink = fn(Level, &str);
+
+const MAX_SINKS: usize = 4;
+const MAX_TARGET_FILTERS: usize = 8;
+/// Longest line kept; the rest of a longer message is cut off.
+const LINE_LENGTH: usize = 256;
+/// Bytes of recent output kept by the ring buffer.
+const RING_SIZE: usize = 16 * 1024;
+
+/// Level for every target named `target` or nested below it, e.g. `kernel::interrupts`.
+#[derive(Debug, Clone, Copy)]
+struct TargetFilter {
+    target: &'static str,
+    level: LevelFilter,
+}
+
+impl TargetFilter {
+    fn matches(&self, target: &str) -> bool {
+        target.strip_prefix(self.target).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    }
+}
+
+struct State {
+    level: LevelFilter,
+    filters: [Option<TargetFilter>; MAX_TARGET_FILTERS],
+    sinks: [Option<Sink>; MAX_SINKS],
+    ring: Ring,
+}
+
+impl State {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        // The most specific filter wins.
+        self.filters
+            .iter()
+            .flatten()
+            .filter(|filter| filter.matches(target))
+            .max_by_key(|filter| filter.target.len())
+            .map_or(self.level, |filter| filter.level)
+    }
+
+    /// Lets through the `log` macros everything at least one filter wants.
+    fn update_max_level(&self) {
+        let most_verbose = self.filters.iter().flatten().map(|filter| filter.level).fold(self.level, Ord::max);
+        log::set_max_level(most_verbose);
+    }
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    level: LevelFilter::Info,
+    filters: [None; MAX_TARGET_FILTERS],
+    sinks: [None; MAX_SINKS],
+    ring: Ring::new(),
+});
+
+struct KernelLogger;
+
+static LOGGER: KernelLogger = KernelLogger;
+
+/// Installs the logger, logging everything at `level` or more severe, and sets up the serial
+/// port it writes to. Later calls only change the level.
+pub fn init(level: LevelFilter) {
+    serial::init();
+    let _ = log::set_logger(&LOGGER);
+    with_state(|state| {
+        state.level = level;
+        state.update_max_level();
+    });
+}
+
+/// Changes the level for targets without a filter of their own.
+pub fn set_level(level: LevelFilter) {
+    with_state(|state| {
+        state.level = level;
+        state.update_max_level();
+    });
+}
+
+/// Sets the level for `target` and the modules inside it, replacing any earlier filter for the
+/// same target. Returns false if all filter slots are taken.
+pub fn set_target_level(target: &'static str, level: LevelFilter) -> bool {
+    with_state(|state| {
+        let slot = state
+            .filters
+            .iter()
+            .position(|filter| filter.is_some_and(|filter| filter.target == target))
+            .or_else(|| state.filters.iter().position(Option::is_none));
+        let Some(slot) = slot else { return false };
+        state.filters[slot] = Some(TargetFilter { target, level });
+        state.update_max_level();
+        true
+    })
+}
+
+/// Also sends every line to `sink`. Sinks run with interrupts disabled and must not log.
+/// Returns false if all sink slots are taken.
+pub fn add_sink(sink: Sink) -> bool {
+    with_state(|state| {
+        let Some(slot) = state.sinks.iter_mut().find(|slot| slot.is_none()) else { return false };
+        *slot = Some(sink);
+        true
+    })
+}
+
+/// Writes out the recent lines kept in memory, oldest first.
+pub fn write_recent(out: &mut impl Write) -> fmt::Result {
+    write_last(out, usize::MAX)
+}
+
+/// Writes out the newest `lines` of the lines kept in memory, oldest first.
+pub fn write_last(out: &mut impl Write, lines: usize) -> fmt::Result {
+    let mut copy = Ring::new();
+    with_state(|state| copy.clone_from(&state.ring));
+    copy.keep_last(lines);
+    for part in copy.contents() {
+        for chunk in part.utf8_chunks() {
+            out.write_str(chunk.valid())?;
+        }
+    }
+    Ok(())
+}
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    without_interrupts(|| f(&mut STATE.lock()))
+}
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= with_state(|state| state.level_for(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        with_state(|state| {
+            if record.level() > state.level_for(record.target()) {
+                return;
+            }
+            let line = format_line(record);
+            let _ = writeln!(serial::Writer, "{}", line.as_str());
+            state.ring.push_line(line.as_str());
+            for sink in state.sinks.iter().flatten() {
+                sink(record.level(), line.as_str());
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// `[   12.345678] INFO  kernel::interrupts: message`
+fn format_line(record: &Record) -> Line {
+    let uptime = time::uptime();
+    let mut line = Line::new();
+    let _ = write!(
+        line,
+        "[{:>5}.{:06}] {:<5} {}: {}",
+        uptime.as_secs(), uptime.subsec_micros(), record.level(), record.target(), record.args(),
+    );
+    line
+}
+
+/// One formatted line on the stack. Text past [LINE_LENGTH] is dropped and marked with `...`.
+struct Line {
+    bytes: [u8; LINE_LENGTH],
+    len: usize,
+    truncated: bool,
+}
+
+impl Line {
+    const ELLIPSIS: &str = "...";
+
+    fn new() -> Self {
+        Self { bytes: [0; LINE_LENGTH], len: 0, truncated: false }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Ok(());
+        }
+        // The end of the buffer is kept free for the ellipsis.
+        let room = LINE_LENGTH - Self::ELLIPSIS.len() - self.len;
+        if s.len() <= room {
+            self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            return Ok(());
+        }
+        let mut end = room;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        for part in [&s[..end], Self::ELLIPSIS] {
+            self.bytes[self.len..self.len + part.len()].copy_from_slice(part.as_bytes());
+            self.len += part.len();
+        }
+        self.truncated = true;
+        Ok(())
+    }
+}
+
+/// The most recent output as newline-terminated lines. When full, whole lines are dropped from
+/// the front to make room.
+#[derive(Clone)]
+struct Ring {
+    bytes: [u8; RING_SIZE],
+    /// Index of the oldest byte.
+    start: usize,
+    len: usize,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self { bytes: [0; RING_SIZE], start: 0, len: 0 }
+    }
+
+    fn push_line(&mut self, line: &str) {
+        // Keep the end of lines too long to fit at all.
+        let line = &line.as_bytes()[line.len().saturating_sub(RING_SIZE - 1)..];
+        let needed = line.len() + 1;
+        while RING_SIZE - self.len < needed {
+            self.drop_line();
+        }
+        for &byte in line.iter().chain(b"\n") {
+            self.bytes[(self.start + self.len) % RING_SIZE] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn drop_line(&mut self) {
+        while self.len > 0 {
+            let byte = self.bytes[self.start];
+            self.start = (self.start + 1) % RING_SIZE;
+            self.len -= 1;
+            if byte == b'\n' {
+                break;
+            }
+        }
+    }
+
+    /// Drops all but the newest `lines` lines.
+    fn keep_last(&mut self, lines: usize) {
+        let mut kept = 0;
+        let mut begin = self.len;
+        while begin > 0 {
+            // Every line ends in a newline, so one found before `begin` ends the line before it.
+            if self.bytes[(self.start + begin - 1) % RING_SIZE] == b'\n' {
+                if kept == lines {
+                    break;
+                }
+                kept += 1;
+            }
+            begin -= 1;
+        }
+        self.start = (self.start + begin) % RING_SIZE;
+        self.len -= begin;
+    }
+
+    /// The contents in order, in at most two pieces because they may wrap around.
+    fn contents(&self) -> [&[u8]; 2] {
+        let end = self.start + self.len;
+        if end <= RING_SIZE {
+            [&self.bytes[self.start..end], &[]]
+        } else {
+            [&self.bytes[self.start..], &self.bytes[..end - RING_SIZE]]
+        }
+    }
+}
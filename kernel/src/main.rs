@@ -8,58 +8,41 @@ extern crate alloc;
 mod screen;
 mod allocator;
 mod frame_allocator;
-mod interrupts;
 mod gdt;
+mod pixel;
+mod text;
+mod console;
+mod shell;
+mod panic_screen;
 
 use core::fmt::Write;
-use core::slice;
 use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
 use bootloader_api::config::Mapping::Dynamic;
 use bootloader_api::info::MemoryRegionKind;
-use kernel::{HandlerTable, serial};
+use kernel::{crash, gdb, HandlerTable, interrupts, is_down, logger};
+use kernel::logger::LevelFilter;
+use log::{debug, info, trace};
+use kernel::time::{Duration, Instant};
 use pc_keyboard::{DecodedKey, KeyCode};
 use x86_64::registers::control::Cr3;
 use x86_64::VirtAddr;
+use x86_64::instructions::interrupts::without_interrupts;
 use crate::frame_allocator::BootInfoFrameAllocator;
-use crate::screen::{ScreenWriter, screenwriter, draw_paddle, draw_ball, draw_center_line, draw_score};
-
-// Game Variables
-static mut SCREEN_WIDTH: usize = 0;
-static mut SCREEN_HEIGHT: usize = 0;
-
-const PADDLE_WIDTH: usize = 15;  
-const PADDLE_HEIGHT: usize = 100; 
-const BALL_SIZE: usize = 12;       
-const PADDLE_SPEED: usize = 50; 
-
-// Player 1 
-static mut PLAYER1_PADDLE_Y: usize = 0; 
-const PLAYER1_PADDLE_X: usize = 30; 
-
-// Player 2 
-static mut PLAYER2_PADDLE_Y: usize = 0; 
-static mut PLAYER2_PADDLE_X: usize = 0; 
-
-// Ball Position and Velocity
-static mut BALL_X: usize = 0; 
-static mut BALL_Y: usize = 0; 
-static mut BALL_VEL_X: isize = 10;  
-static mut BALL_VEL_Y: isize = 10;
-
-// Player Scores
-static mut PLAYER1_SCORE: usize = 0; 
-static mut PLAYER2_SCORE: usize = 0;
-
-// Game State
-#[derive(PartialEq)]
-enum GameState {
-    StartScreen,
-    Playing,
-    GameOver
+use spin::Mutex;
+use pong::{Fixed, GameState, Hold, Input, Mode, Player, PongGame, Vec2, Viewport, FIELD_HEIGHT, FIELD_WIDTH};
+use gfx::{Color, Rect};
+use crate::text::{FontWeight, HAlign, TextBuffer, TextStyle, VAlign};
+use crate::screen::{ScreenWriter, screenwriter, draw_paddle, draw_ball, draw_center_line, draw_score, field_point};
+
+static GAME: Mutex<Option<PongGame>> = Mutex::new(None);
+
+/// Time played in the match in progress and length of the last finished one. Only steps taken
+/// while the match is running count, so time spent paused is left out.
+struct MatchClock {
+    played: Duration,
+    last: Duration,
 }
-static mut GAME_STATE: GameState = GameState::StartScreen;
-const WINNING_SCORE: usize = 5;
-
+static MATCH_CLOCK: Mutex<MatchClock> = Mutex::new(MatchClock { played: Duration::ZERO, last: Duration::ZERO });
 
 const BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
@@ -71,265 +54,353 @@ entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 
 
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
-    writeln!(serial(), "Entered kernel with boot info: {boot_info:?}").unwrap();
-    writeln!(serial(), "Frame Buffer: {:p}", boot_info.framebuffer.as_ref().unwrap().buffer()).unwrap();
-
-    
-    let frame_info = boot_info.framebuffer.as_ref().unwrap().info();
-    let framebuffer = boot_info.framebuffer.as_mut().unwrap();
-    screen::init(framebuffer);
-
-    for r in boot_info.memory_regions.iter() {
-        writeln!(serial(), "{:?} {:?} {:?} {}", r, r.start as *mut u8, r.end as *mut usize, r.end-r.start).unwrap();
-    }
+    logger::init(LevelFilter::Debug);
+    crash::init(boot_info.kernel_image_offset);
+    info!("Kernel image offset {:#x}", boot_info.kernel_image_offset);
+    trace!("Entered kernel with boot info: {boot_info:?}");
+    debug!("Frame Buffer: {:p}", boot_info.framebuffer.as_ref().unwrap().buffer());
 
-    // Initialize screen dimensions
-    unsafe {
-        SCREEN_WIDTH = frame_info.width as usize;
-        SCREEN_HEIGHT = frame_info.height as usize;
-        
-        PLAYER1_PADDLE_Y = SCREEN_HEIGHT / 2 - PADDLE_HEIGHT / 2;
-        PLAYER2_PADDLE_X = SCREEN_WIDTH - PADDLE_WIDTH - 30;
-        PLAYER2_PADDLE_Y = SCREEN_HEIGHT / 2 - PADDLE_HEIGHT / 2;
-        
-        BALL_X = SCREEN_WIDTH / 2;
-        BALL_Y = SCREEN_HEIGHT / 2;
-    }
+    let mut game = PongGame::new(FIELD_WIDTH, FIELD_HEIGHT);
+    game.seed = unsafe { core::arch::x86_64::_rdtsc() } as u32; // vary the computer's aim between boots
+    *GAME.lock() = Some(game);
 
     let usable_region = boot_info.memory_regions.iter().filter(|x|x.kind == MemoryRegionKind::Usable).last().unwrap();
-    writeln!(serial(), "{usable_region:?}").unwrap();
+    debug!("{usable_region:?}");
 
     let physical_offset = boot_info.physical_memory_offset.take().expect("Failed to find physical memory offset");
     let ptr = (physical_offset + usable_region.start) as *mut u8;
-    writeln!(serial(), "Physical memory offset: {:X}; usable range: {:p}", physical_offset, ptr).unwrap();
-
-    // print out values stored in specific memory address
-    let vault = unsafe { slice::from_raw_parts_mut(ptr, 100) };
-    vault[0] = 65;
-    vault[1] = 66;
-    //writeln!(Writer, "{} {}", vault[0] as char, vault[1] as char).unwrap();
+    debug!("Physical memory offset: {:X}; usable range: {:p}", physical_offset, ptr);
 
     //read CR3 for current page table
     let cr3 = Cr3::read().0.start_address().as_u64();
-    writeln!(serial(), "CR3 read: {:#x}", cr3).unwrap();
-
-    let cr3_page = unsafe { slice::from_raw_parts_mut((cr3 + physical_offset) as *mut usize, 6) };
-    writeln!(serial(), "CR3 Page table virtual address {cr3_page:#p}").unwrap();
+    debug!("CR3 read: {:#x}", cr3);
+
+    // The screen's back buffer and the console live on the heap, so it grows with the screen.
+    let info = boot_info.framebuffer.as_ref().unwrap().info();
+    let heap_size = info.height * info.stride * info.bytes_per_pixel
+        + console::heap_needed(info.width, info.height, info.bytes_per_pixel)
+        + allocator::HEAP_SLACK;
+    let region_size = (usable_region.end - usable_region.start) as usize;
+    assert!(
+        heap_size <= region_size,
+        "a {}x{} screen needs a {} KiB heap, but the last usable region at {:#x} has only {} KiB",
+        info.width, info.height, heap_size / 1024, usable_region.start, region_size / 1024
+    );
+    allocator::init_heap((physical_offset + usable_region.start) as usize, heap_size);
 
-    allocator::init_heap((physical_offset + usable_region.start) as usize);
+    let framebuffer = boot_info.framebuffer.as_mut().unwrap();
+    screen::init(framebuffer);
+    crash::set_screen(panic_screen::show);
+    console::init(screenwriter());
+    // Catch the console up on what was logged before it existed.
+    let _ = logger::write_recent(&mut console::Writer);
+    logger::add_sink(console::log_sink);
+    info!("{}x{} framebuffer, F1 shows or hides this console", screenwriter().width(), screenwriter().height());
 
     let rsdp = boot_info.rsdp_addr.take();
     let mut mapper = frame_allocator::init(VirtAddr::new(physical_offset));
-    let mut frame_allocator = BootInfoFrameAllocator::new(&boot_info.memory_regions);
+    // The heap took the start of the last usable region; page tables and stacks must not.
+    let heap = usable_region.start..usable_region.start + heap_size as u64;
+    let mut frame_allocator = BootInfoFrameAllocator::new(&boot_info.memory_regions, heap);
     
-    gdt::init();
+    gdt::init(&mut mapper, &mut frame_allocator);
+
+    gdb::init(VirtAddr::new(physical_offset));
+    shell::init(shell::Machine { memory_regions: &boot_info.memory_regions, physical_offset });
 
     let lapic_ptr = interrupts::init_apic(rsdp.expect("Failed to get RSDP address") as usize, physical_offset, &mut mapper, &mut frame_allocator);
     HandlerTable::new()
+        .timer_hz(60)
         .keyboard(key)
-        .timer(tick)
+        .serial(shell::receive)
         .startup(start)
+        .cpu_loop(game_loop)
         .start(lapic_ptr)
 }
 
 fn start() {
-    unsafe {
-        let writer = screenwriter();
-        writer.clear();
-        GAME_STATE = GameState::StartScreen;
-    }
+    let writer = screenwriter();
+    writer.clear();
 }
 
-fn draw_start_screen(writer: &mut ScreenWriter) {
-    let screen_width = unsafe { SCREEN_WIDTH };
-    let screen_height = unsafe { SCREEN_HEIGHT };
-
-    writer.write_large_text("PONG", screen_width / 2 - 60, screen_height / 3, 255, 255, 255);
-    writer.write_large_text("Press SPACE to Start", screen_width / 2 - 200, screen_height / 2, 255, 255, 255);
+/// Large bold headline, centred on its anchor's baseline.
+const TITLE_STYLE: TextStyle = TextStyle::new(Color::WHITE)
+    .scale(6)
+    .weight(FontWeight::Bold)
+    .align(HAlign::Centre, VAlign::Baseline);
+/// Menu text, centred below its anchor.
+const MENU_STYLE: TextStyle = TextStyle::new(Color::WHITE).scale(3).align(HAlign::Centre, VAlign::Top);
+
+fn draw_start_screen(writer: &mut ScreenWriter, viewport: &Viewport, game: &PongGame) {
+    let (centre, title_y) = field_point(viewport, game.field_width / 2, game.field_height / 3);
+    let (_, menu_y) = field_point(viewport, 0, game.field_height / 2);
+    writer.draw_text("PONG", centre, title_y, &TITLE_STYLE.fit_to(viewport));
+
+    let mut menu = TextBuffer::<128>::new();
+    let _ = write!(menu, "Press SPACE to Start\n\n");
+    let _ = match game.mode {
+        Mode::SinglePlayer => write!(menu, "1 Player  (press 2)\nComputer: {}\nPress D to change", game.difficulty.name()),
+        Mode::TwoPlayer => write!(menu, "2 Players (press 1)"),
+    };
+    writer.draw_text(menu.as_str(), centre, menu_y, &MENU_STYLE.fit_to(viewport));
 }
 
-fn draw_game_over_screen(writer: &mut ScreenWriter) {
-    let screen_width = unsafe { SCREEN_WIDTH };
-    let screen_height = unsafe { SCREEN_HEIGHT };
-    writer.write_large_text("GAME OVER", screen_width / 2 - 120, screen_height / 3, 255, 255, 255);
-    let winner_text = if unsafe { PLAYER1_SCORE >= WINNING_SCORE } {
-        "Player 1 Wins"
-    } else {
-        "Player 2 Wins"
-    };
-    writer.write_large_text(winner_text, screen_width / 2 - 150, screen_height / 2, 255, 255, 255);
-    writer.write_large_text("Press SPACE to Restart", screen_width / 2 - 200, screen_height / 2 + 100, 255, 255, 255);
+fn draw_game_over_screen(writer: &mut ScreenWriter, viewport: &Viewport, game: &PongGame, match_time: Duration) {
+    let (centre, title_y) = field_point(viewport, game.field_width / 2, game.field_height / 3);
+    let (_, text_y) = field_point(viewport, 0, game.field_height / 2);
+    writer.draw_text("GAME OVER", centre, title_y, &TITLE_STYLE.fit_to(viewport));
+
+    let winner = if game.winner() == Some(Player::One) { 1 } else { 2 };
+    let seconds = match_time.as_secs();
+    let mut text = TextBuffer::<128>::new();
+    let _ = write!(
+        text,
+        "Player {winner} Wins\n\nPress SPACE to Restart\nMatch time {}:{:02}",
+        seconds / 60, seconds % 60,
+    );
+    writer.draw_text(text.as_str(), centre, text_y, &MENU_STYLE.fit_to(viewport));
 }
 
-fn init_game() {
-    unsafe {
-        let writer = screenwriter();
+/// Length of one simulation step. The game advances by exactly this much time per step,
+/// however fast or slow frames are drawn.
+const STEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// Most steps simulated between two frames. After a longer stall the lost time is dropped
+/// instead of fast-forwarding the game to catch up.
+const MAX_STEPS_PER_FRAME: u32 = 5;
+/// How often frame statistics are logged over serial.
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+/// Length of a screen transition: half fading the old screen out, half fading the new one in.
+const FADE: Duration = Duration::from_millis(400);
+/// Number of past ball positions drawn behind it.
+const TRAIL_LENGTH: usize = 8;
+
+/// Runs the game on the CPU loop: simulates fixed steps for the real time that has passed,
+/// then draws a frame blended between the last two steps. Until another step is due the CPU
+/// halts, so it sleeps between timer ticks.
+fn game_loop() -> ! {
+    let mut current = without_interrupts(|| GAME.lock().clone()).expect("game is created before the loop starts");
+    let mut previous = current.clone();
+    let mut last_time = Instant::now();
+    let mut lag = Duration::ZERO;
+    let mut last_report = last_time;
+    let mut effects = Effects { trail: Trail::EMPTY, fade: None };
+    let mut shown = current.clone();
+
+    loop {
+        let now = Instant::now();
+        lag += now - last_time;
+        last_time = now;
+
+        let mut steps = 0;
+        while lag >= STEP && steps < MAX_STEPS_PER_FRAME {
+            previous = current;
+            current = without_interrupts(update);
+            effects.trail.follow(&previous, &current);
+            lag -= STEP;
+            steps += 1;
+        }
+        if lag >= STEP {
+            lag = Duration::ZERO;
+        }
+        if steps == 0 {
+            // Menu keys may have changed the state since the last step.
+            current = without_interrupts(|| GAME.lock().clone()).unwrap_or(current);
+        }
 
-        let screen_width = SCREEN_WIDTH;
-        let screen_height = SCREEN_HEIGHT;
+        let alpha = Fixed::from_ratio(lag.as_nanos() as i32, STEP.as_nanos() as i32);
+        let match_time = without_interrupts(|| MATCH_CLOCK.lock().last);
+        if current.state != shown.state {
+            effects.fade = Some((shown, now));
+        }
+        render(screenwriter(), &mut effects, &previous, &current, alpha, match_time);
+        shown = current.clone();
+        shell::run_pending();
 
-        BALL_X = screen_width / 2;
-        BALL_Y = screen_height / 2;
+        if now - last_report >= STATS_INTERVAL {
+            report_render_stats(now - last_report);
+            last_report = now;
+        }
 
-        PLAYER1_PADDLE_Y = screen_height / 2 - PADDLE_HEIGHT / 2;
-        PLAYER2_PADDLE_Y = screen_height / 2 - PADDLE_HEIGHT / 2;
+        if lag + (Instant::now() - last_time) < STEP {
+            // Any interrupt wakes it, the next timer tick at the latest.
+            x86_64::instructions::hlt();
+        }
+    }
+}
 
-        PLAYER1_SCORE = 0;
-        PLAYER2_SCORE = 0;
+/// Logs how many frames were drawn and how much of the screen each one had to copy.
+fn report_render_stats(elapsed: Duration) {
+    let writer = screenwriter();
+    let stats = writer.take_present_stats();
+    let full_frame = writer.width() * writer.height();
+    let per_frame = stats.pixels / stats.frames.max(1);
+    debug!(
+        "{} frames in {:?}, {} pixels pushed per frame ({}% of {})",
+        stats.frames, elapsed, per_frame, per_frame * 100 / full_frame.max(1) as u64, full_frame,
+    );
+}
 
-        BALL_VEL_X = 10;  
-        BALL_VEL_Y = 10;
+/// Advances the game by one step and returns a copy of it to draw from.
+fn update() -> PongGame {
+    let mut game = GAME.lock();
+    let game = game.as_mut().expect("game is created before the loop starts");
 
-        GAME_STATE = GameState::Playing;
+    // Player 1 controls (W/S), Player 2 controls (Arrow Up/Down)
+    game.apply_input(Input::Hold(Player::One, Hold::from_keys(is_down(KeyCode::W), is_down(KeyCode::S))));
+    game.apply_input(Input::Hold(Player::Two, Hold::from_keys(is_down(KeyCode::ArrowUp), is_down(KeyCode::ArrowDown))));
 
-        writer.clear();
-        draw_paddle(writer, PLAYER1_PADDLE_X, PLAYER1_PADDLE_Y, 255, 255, 255);
-        draw_paddle(writer, PLAYER2_PADDLE_X, PLAYER2_PADDLE_Y, 255, 255, 255);
+    let was_playing = game.state == GameState::Playing;
+    if was_playing && !game.paused {
+        MATCH_CLOCK.lock().played += STEP;
     }
-}
+    game.step();
 
-fn reset_ball() {
-    unsafe {
-        BALL_X = SCREEN_WIDTH / 2;
-        BALL_Y = SCREEN_HEIGHT / 2;
-
-        BALL_VEL_X = if BALL_VEL_X > 0 { -10 } else { 10 };
-        BALL_VEL_Y = if BALL_VEL_Y > 0 { -10 } else { 10 };
+    if was_playing && game.state == GameState::GameOver {
+        let mut clock = MATCH_CLOCK.lock();
+        clock.last = core::mem::take(&mut clock.played);
+        info!("Match over {}-{} after {:?}", game.player1_score, game.player2_score, clock.last);
     }
+    game.clone()
 }
 
+/// Frame-to-frame state that only changes how the game looks.
+struct Effects {
+    trail: Trail,
+    /// The screen being faded out, and when the transition started.
+    fade: Option<(PongGame, Instant)>,
+}
 
-fn tick() {
-    unsafe {
-        let writer = screenwriter();
+/// Recent ball positions, oldest first, one per simulation step.
+struct Trail {
+    points: [Vec2; TRAIL_LENGTH],
+    len: usize,
+}
 
-        match GAME_STATE {
-            GameState::StartScreen => {
-                draw_start_screen(writer);
-            }
-            GameState::Playing => {
-                let ball_x = BALL_X;
-                let ball_y = BALL_Y;
-                draw_ball(writer, ball_x, ball_y, 0, 0, 0);
-                BALL_X = (BALL_X as isize + BALL_VEL_X) as usize;
-                BALL_Y = (BALL_Y as isize + BALL_VEL_Y) as usize;
-
-                const PADDLE_BUFFER: usize = 15; 
-
-                
-                // Ball collision with walls (top/bottom)
-                if BALL_Y <= 5 || BALL_Y + BALL_SIZE >= SCREEN_HEIGHT {
-                    BALL_VEL_Y = -BALL_VEL_Y;
-                    if BALL_Y <= 0 {
-                        BALL_Y = 0;  
-                    }
-                }
-            
-                // Ball collision with Player 1 
-                if BALL_X <= PLAYER1_PADDLE_X + PADDLE_WIDTH + PADDLE_BUFFER && 
-                    BALL_Y + BALL_SIZE >= PLAYER1_PADDLE_Y - PADDLE_BUFFER &&    
-                    BALL_Y <= PLAYER1_PADDLE_Y + PADDLE_HEIGHT + PADDLE_BUFFER {
-                    BALL_VEL_X = BALL_VEL_X.abs(); 
-                }
-                
-                // Ball collision with Player 2 
-                if BALL_X + BALL_SIZE >= PLAYER2_PADDLE_X - PADDLE_BUFFER && 
-                    BALL_Y + BALL_SIZE >= PLAYER2_PADDLE_Y - PADDLE_BUFFER &&  
-                    BALL_Y <= PLAYER2_PADDLE_Y + PADDLE_HEIGHT + PADDLE_BUFFER {
-                    BALL_VEL_X = -BALL_VEL_X.abs(); 
-                }
-                
-                
-                if BALL_X <= PADDLE_WIDTH {
-                    PLAYER2_SCORE += 1;
-                    if PLAYER2_SCORE >= WINNING_SCORE {
-                        GAME_STATE = GameState::GameOver; 
-                    } else {
-                        reset_ball();
-                    }
-                } else if BALL_X >= SCREEN_WIDTH {
-                    PLAYER1_SCORE += 1;
-                    if PLAYER1_SCORE >= WINNING_SCORE {
-                        GAME_STATE = GameState::GameOver; 
-                    } else {
-                        reset_ball();
-                    }
-                }
-
-                draw_center_line(writer);
-                draw_score(writer, PLAYER1_SCORE, PLAYER2_SCORE);
-                draw_ball(writer, BALL_X, BALL_Y, 255, 255, 255);
-            }
-            GameState::GameOver => {
-                BALL_VEL_X = 0;
-                BALL_VEL_Y = 0;
-                draw_game_over_screen(writer);
+impl Trail {
+    const EMPTY: Trail = Trail { points: [Vec2::ZERO; TRAIL_LENGTH], len: 0 };
+
+    /// Records where the ball was before the last step, or starts over after a serve.
+    fn follow(&mut self, previous: &PongGame, current: &PongGame) {
+        if !same_rally(previous, current) {
+            self.len = 0;
+        } else if !current.paused {
+            if self.len == TRAIL_LENGTH {
+                self.points.rotate_left(1);
+                self.len -= 1;
             }
+            self.points[self.len] = previous.ball.pos;
+            self.len += 1;
         }
-        
+    }
+
+    fn points(&self) -> &[Vec2] {
+        &self.points[..self.len]
     }
 }
 
-fn key(key: DecodedKey) {
-    unsafe {
-        let writer = screenwriter();
-
-        match GAME_STATE {
-            GameState::StartScreen => {
-                match key {
-                    DecodedKey::Unicode(' ') => {
-                        init_game();
-                    },
-                    _ => {}
-                }
-            }
-            GameState::Playing => {
-                match key {
-                    // Player 1 controls (W/S)
-                    DecodedKey::Unicode('w') => {
-                        if PLAYER1_PADDLE_Y > PADDLE_SPEED {
-                            draw_paddle(writer, PLAYER1_PADDLE_X, PLAYER1_PADDLE_Y, 0, 0, 0); // Erase old paddle
-                            PLAYER1_PADDLE_Y -= PADDLE_SPEED;
-                            draw_paddle(writer, PLAYER1_PADDLE_X, PLAYER1_PADDLE_Y, 255, 255, 255); // Draw new paddle
-                        }
-                    }
-                    
-                    DecodedKey::Unicode('s') => {
-                        if PLAYER1_PADDLE_Y + PADDLE_HEIGHT + PADDLE_SPEED < SCREEN_HEIGHT {
-                            draw_paddle(writer, PLAYER1_PADDLE_X, PLAYER1_PADDLE_Y, 0, 0, 0); // Erase old paddle
-                            PLAYER1_PADDLE_Y += PADDLE_SPEED;
-                            draw_paddle(writer, PLAYER1_PADDLE_X, PLAYER1_PADDLE_Y, 255, 255, 255); // Draw new paddle
-                        }
-                    }
-        
-                    // Player 2 controls (Arrow Up/Down)
-                    DecodedKey::RawKey(KeyCode::ArrowUp) => {
-                        if PLAYER2_PADDLE_Y > PADDLE_SPEED {
-                            draw_paddle(writer, PLAYER2_PADDLE_X, PLAYER2_PADDLE_Y, 0, 0, 0); // Erase old paddle
-                            PLAYER2_PADDLE_Y -= PADDLE_SPEED;
-                            draw_paddle(writer, PLAYER2_PADDLE_X, PLAYER2_PADDLE_Y, 255, 255, 255); // Draw new paddle
-                        }
-                    }
-                    DecodedKey::RawKey(KeyCode::ArrowDown) => {
-                        if PLAYER2_PADDLE_Y + PADDLE_HEIGHT + PADDLE_SPEED < SCREEN_HEIGHT {
-                            draw_paddle(writer, PLAYER2_PADDLE_X, PLAYER2_PADDLE_Y, 0, 0, 0); // Erase old paddle
-                            PLAYER2_PADDLE_Y += PADDLE_SPEED;
-                            draw_paddle(writer, PLAYER2_PADDLE_X, PLAYER2_PADDLE_Y, 255, 255, 255); // Draw new paddle
-                        }
-                    }
-        
-                    _ => {}
-                }
+/// True while both snapshots belong to the same point of a match, so positions can be blended.
+fn same_rally(previous: &PongGame, current: &PongGame) -> bool {
+    previous.state == GameState::Playing
+        && current.state == GameState::Playing
+        && (previous.player1_score, previous.player2_score) == (current.player1_score, current.player2_score)
+}
+
+/// Draws the whole scene into the back buffer, with any transition or pause overlay, and shows it.
+fn render(writer: &mut ScreenWriter, effects: &mut Effects, previous: &PongGame, current: &PongGame, alpha: Fixed, match_time: Duration) {
+    writer.clear();
+    // Recomputed every frame so the layout never goes stale; it is only a few divisions.
+    let viewport = Viewport::fit(FIELD_WIDTH, FIELD_HEIGHT, writer.width(), writer.height());
+
+    let elapsed = effects.fade.as_ref().map(|(_, started)| started.elapsed());
+    match (&effects.fade, elapsed) {
+        (Some((old, _)), Some(elapsed)) if elapsed < FADE / 2 => {
+            draw_scene(writer, &viewport, old, old, Fixed::ZERO, match_time, &Trail::EMPTY);
+            dim(writer, share(elapsed, FADE / 2));
+        }
+        (Some(_), Some(elapsed)) if elapsed < FADE => {
+            draw_scene(writer, &viewport, previous, current, alpha, match_time, &effects.trail);
+            dim(writer, 255 - share(elapsed - FADE / 2, FADE / 2));
+        }
+        _ => {
+            effects.fade = None;
+            draw_scene(writer, &viewport, previous, current, alpha, match_time, &effects.trail);
+        }
+    }
+
+    if current.state == GameState::Playing && current.paused {
+        dim(writer, 160);
+        let style = TITLE_STYLE.align(HAlign::Centre, VAlign::Middle).fit_to(&viewport);
+        let (x, y) = field_point(&viewport, current.field_width / 2, current.field_height / 2);
+        writer.draw_text("PAUSED", x, y, &style);
+    }
+    console::draw(writer);
+    writer.present();
+}
+
+fn draw_scene(writer: &mut ScreenWriter, viewport: &Viewport, previous: &PongGame, current: &PongGame, alpha: Fixed, match_time: Duration, trail: &Trail) {
+    match current.state {
+        GameState::StartScreen => draw_start_screen(writer, viewport, current),
+        GameState::GameOver => draw_game_over_screen(writer, viewport, current, match_time),
+        GameState::Playing => {
+            // A serve teleports the ball, so only blend between steps of the same rally.
+            let blend = same_rally(previous, current);
+            let at = |from: Vec2, to: Vec2| {
+                let lerp = |a: Fixed, b: Fixed| if blend { a + (b - a) * alpha } else { b };
+                Vec2::new(lerp(from.x, to.x), lerp(from.y, to.y))
+            };
+
+            draw_center_line(writer, viewport);
+            draw_score(writer, viewport, current.player1_score, current.player2_score);
+            for (i, point) in trail.points().iter().enumerate() {
+                let opacity = ((i + 1) * 128 / (TRAIL_LENGTH + 1)) as u8;
+                draw_ball(writer, viewport, *point, Color::WHITE.with_alpha(opacity));
             }
-            GameState::GameOver => {
-                match key {
-                    DecodedKey::Unicode(' ') => {
-                        init_game();
-                    },
-                    _ => {}
-                }
+            draw_ball(writer, viewport, at(previous.ball.pos, current.ball.pos), Color::WHITE);
+            for (from, to) in [(&previous.player1, &current.player1), (&previous.player2, &current.player2)] {
+                draw_paddle(writer, viewport, at(Vec2::new(from.x, from.y), Vec2::new(to.x, to.y)), Color::WHITE);
             }
         }
     }
 }
 
+/// Darkens everything drawn so far; 0 leaves it alone, 255 turns it black.
+fn dim(writer: &mut ScreenWriter, amount: u8) {
+    let screen = Rect::new(0, 0, writer.width(), writer.height());
+    writer.fill_rect(screen, Color::BLACK.with_alpha(amount));
+}
+
+/// How far `elapsed` is through `total`, from 0 to 255.
+fn share(elapsed: Duration, total: Duration) -> u8 {
+    (elapsed.as_nanos() * 255 / total.as_nanos().max(1)).min(255) as u8
+}
+
+/// Lines moved by Page Up and Page Down in the console.
+const CONSOLE_PAGE: isize = 10;
+
+fn key(key: DecodedKey) {
+    match key {
+        DecodedKey::RawKey(KeyCode::F1) => return console::toggle(),
+        DecodedKey::RawKey(KeyCode::PageUp) => return console::scroll(CONSOLE_PAGE),
+        DecodedKey::RawKey(KeyCode::PageDown) => return console::scroll(-CONSOLE_PAGE),
+        _ => {}
+    }
+
+    // Paddles are driven by the held-key table in `update`, only menu keys arrive here.
+    let input = match key {
+        DecodedKey::Unicode(' ') => Input::Start,
+        DecodedKey::Unicode('1') => Input::SelectMode(Mode::SinglePlayer),
+        DecodedKey::Unicode('2') => Input::SelectMode(Mode::TwoPlayer),
+        DecodedKey::Unicode('d') => Input::CycleDifficulty,
+        DecodedKey::Unicode('p') => Input::TogglePause,
+        _ => return,
+    };
+
+    let mut game = GAME.lock();
+    let Some(game) = game.as_mut() else { return };
+
+    let was_playing = game.state == GameState::Playing;
+    game.apply_input(input);
+    if !was_playing && game.state == GameState::Playing {
+        MATCH_CLOCK.lock().played = Duration::ZERO;
+    }
+}
@@ -0,0 +1,33 @@
+// Panic screen: the panic message, where it happened and the backtrace, drawn straight into the
+// framebuffer over whatever the game left there. Fatal CPU exceptions panic with their register
+// dump, so they end up here too. Installed with `crash::set_screen`.
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use kernel::crash::{self, Backtrace};
+use gfx::Color;
+use crate::screen;
+use crate::text::{FontWeight, TextStyle};
+
+const BACKGROUND: Color = Color::rgb(0, 0, 170);
+const MARGIN: usize = 32;
+
+pub fn show(info: &PanicInfo, backtrace: &Backtrace) {
+    let Some(mut screen) = screen::take_over_for_panic() else { return };
+    screen.clear(BACKGROUND, MARGIN);
+    let text = TextStyle::new(Color::WHITE);
+
+    screen.set_style(text.scale(2).weight(FontWeight::Bold));
+    let _ = writeln!(screen, "The kernel panicked");
+    screen.set_style(text);
+    if let Some(location) = info.location() {
+        let _ = writeln!(screen, "at {location}");
+    }
+    let _ = writeln!(screen, "\n{}\n", info.message());
+
+    let _ = writeln!(screen, "Backtrace (kernel image offset {:#x}):", crash::kernel_image_offset());
+    for (index, address) in backtrace.addresses().iter().enumerate() {
+        let _ = writeln!(screen, "  #{index:<2} {address:#018x}");
+    }
+    let _ = writeln!(screen, "\nThe same report is on the serial port. Restart the machine to play again.");
+}
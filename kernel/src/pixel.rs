@@ -0,0 +1,19 @@
+// Picks the pixel layout for the framebuffer mode the firmware reported. The colours and their
+// encoding are in `gfx::pixel`.
+
+use bootloader_api::info::{FrameBufferInfo, PixelFormat};
+use gfx::PixelEncoder;
+
+pub fn encoder_for(info: &FrameBufferInfo) -> PixelEncoder {
+    let bytes_per_pixel = info.bytes_per_pixel;
+    match info.pixel_format {
+        PixelFormat::Rgb => PixelEncoder::channels([0, 8, 16], bytes_per_pixel),
+        PixelFormat::Bgr => PixelEncoder::channels([16, 8, 0], bytes_per_pixel),
+        PixelFormat::U8 => PixelEncoder::gray(),
+        PixelFormat::Unknown { red_position, green_position, blue_position } => {
+            PixelEncoder::channels([red_position, green_position, blue_position], bytes_per_pixel)
+        }
+        // Formats added to the bootloader later are most likely plain 8-bit RGB variants.
+        _ => PixelEncoder::channels([0, 8, 16], bytes_per_pixel),
+    }
+}
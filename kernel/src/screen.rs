@@ -1,21 +1,17 @@
 // Original code from rust-osdev/bootloader crate https://github.com/rust-osdev/bootloader
 
-use core::{fmt, ptr};
-use noto_sans_mono_bitmap::{FontWeight, get_raster, RasterizedChar};
-use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
-use noto_sans_mono_bitmap::RasterHeight::Size16;
+use core::fmt::Write;
+use core::ops::{Deref, DerefMut};
+use noto_sans_mono_bitmap::get_raster;
+use bootloader_api::info::{FrameBuffer, FrameBufferInfo};
 use kernel::RacyCell;
-use crate::alloc::string::ToString;
+use gfx::{Canvas, Color, PixelEncoder, Rect};
+use crate::pixel;
+use crate::text::{self, measure_text, HAlign, TextBuffer, TextMetrics, TextStyle, VAlign};
+use pong::game::{BALL_SIZE, FIELD_HEIGHT, FIELD_WIDTH, PADDLE_HEIGHT, PADDLE_WIDTH};
+use pong::{Fixed, Vec2, Viewport};
 
 static WRITER: RacyCell<Option<ScreenWriter>> = RacyCell::new(None);
-pub struct Writer;
-
-impl fmt::Write for Writer {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        let writer = unsafe { WRITER.get_mut() }.as_mut().unwrap();
-        writer.write_str(s)
-    }
-}
 
 pub fn screenwriter() -> &'static mut ScreenWriter {
     let writer = unsafe { WRITER.get_mut() }.as_mut().unwrap();
@@ -30,167 +26,119 @@ pub fn init(buffer: &'static mut FrameBuffer) {
     *unsafe { WRITER.get_mut() } = Some(writer);
 }
 
-const LINE_SPACING: usize = 0;
+/// Pixels copied to the framebuffer by [ScreenWriter::present].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PresentStats {
+    pub frames: u64,
+    pub pixels: u64,
+    /// Pixels copied by the most recent frame alone.
+    pub last_frame: usize,
+}
 
+/// Draws into a back buffer in RAM; nothing shows up on screen until [ScreenWriter::present]
+/// copies the finished frame to the framebuffer. The drawing itself is done by the [Canvas] it
+/// derefs to, which keeps track of what changed, so `present` only copies that.
 pub struct ScreenWriter {
     framebuffer: &'static mut [u8],
+    canvas: Canvas,
     info: FrameBufferInfo,
-    x_pos: usize,
-    y_pos: usize,
+    stats: PresentStats,
 }
 
 impl ScreenWriter {
     pub fn new(framebuffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
-        let mut logger = Self {
+        Self {
+            canvas: Canvas::new(info.width, info.height, info.stride, info.bytes_per_pixel, pixel::encoder_for(&info)),
             framebuffer,
             info,
-            x_pos: 0,
-            y_pos: 0,
-        };
-        logger.clear();
-        logger
+            stats: PresentStats::default(),
+        }
     }
 
-    fn newline(&mut self) {
-        self.y_pos += Size16 as usize + LINE_SPACING;
-        self.carriage_return()
-    }
+    /// Shows everything drawn since the last call by copying the changed parts of the back
+    /// buffer to the framebuffer.
+    pub fn present(&mut self) {
+        let dirty = self.canvas.take_dirty();
+        for rect in dirty.iter() {
+            for y in rect.y..rect.bottom() {
+                let row = self.canvas.row_bytes(rect, y);
+                self.framebuffer[row.clone()].copy_from_slice(&self.canvas.pixels()[row]);
+            }
+        }
+        let pixels = dirty.area();
 
-    fn carriage_return(&mut self) {
-        self.x_pos = 0;
+        self.stats.frames += 1;
+        self.stats.pixels += pixels as u64;
+        self.stats.last_frame = pixels;
     }
 
-    pub fn clear(&mut self) {
-        self.x_pos = 0;
-        self.y_pos = 0;
-        self.framebuffer.fill(0);
+    /// Returns the counters gathered since the previous call and starts over.
+    pub fn take_present_stats(&mut self) -> PresentStats {
+        core::mem::take(&mut self.stats)
     }
+}
 
-    fn width(&self) -> usize {
-        self.info.width.into()
-    }
+impl Deref for ScreenWriter {
+    type Target = Canvas;
 
-    fn height(&self) -> usize {
-        self.info.height.into()
+    fn deref(&self) -> &Canvas {
+        &self.canvas
     }
+}
 
-    fn write_char(&mut self, c: char) {
-        match c {
-            '\n' => self.newline(),
-            '\r' => self.carriage_return(),
-            c => {
-                match get_raster(c, FontWeight::Regular, Size16) {
-                    Some(bitmap_char) => {
-                        if self.x_pos + bitmap_char.width() > self.width() {
-                            self.newline();
-                        }
-                        if self.y_pos + bitmap_char.height() > self.height() {
-                            self.clear();
-                        }
-                        self.write_rendered_char(bitmap_char);
-                    },
-                    None => {}
-                }
-            }
-        }
+impl DerefMut for ScreenWriter {
+    fn deref_mut(&mut self) -> &mut Canvas {
+        &mut self.canvas
     }
+}
 
-    fn write_rendered_char(&mut self, rendered_char: RasterizedChar) {
-        for (y, row) in rendered_char.raster().iter().enumerate() {
-            for (x, byte) in row.iter().enumerate() {
-                self.write_pixel(self.x_pos + x, self.y_pos + y, *byte);
-            }
-        }
-        self.x_pos += rendered_char.width();
+/// Fills a box given in field units, clipped to the viewport.
+fn fill_field_rect(writer: &mut ScreenWriter, viewport: &Viewport, pos: Vec2, size: Vec2, color: Color) {
+    if let Some(rect) = viewport.rect(pos, size) {
+        writer.fill_rect(Rect::new(rect.x, rect.y, rect.width, rect.height), color);
     }
+}
 
-    pub fn write_pixel(&mut self, x: usize, y: usize, intensity: u8) {
-        let pixel_offset = y * usize::from(self.info.stride) + x;
-        let color = match self.info.pixel_format {
-            PixelFormat::Rgb => [intensity / 4, intensity, intensity / 2, 0],
-            PixelFormat::Bgr => [intensity / 2, intensity, intensity / 4, 0],
-            other => {
-                // set a supported (but invalid) pixel format before panicking to avoid a double
-                // panic; it might not be readable though
-                self.info.pixel_format = PixelFormat::Rgb;
-                panic!("pixel format {:?} not supported in logger", other)
-            }
-        };
-        let bytes_per_pixel = self.info.bytes_per_pixel;
-        let byte_offset = pixel_offset * usize::from(bytes_per_pixel);
-        self.framebuffer[byte_offset..(byte_offset + usize::from(bytes_per_pixel))]
-            .copy_from_slice(&color[..usize::from(bytes_per_pixel)]);
-        let _ = unsafe { ptr::read_volatile(&self.framebuffer[byte_offset]) };
-    }
 
-    pub fn draw_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8) {
-        if x >= self.info.width || y >= self.info.height {
-            return;
-        }
-    
-        let pixel_offset = match y.checked_mul(self.info.stride.into()) {
-            Some(offset) => offset + x,
-            None => return, 
-        };
-    
-        let byte_offset = match pixel_offset.checked_mul(self.info.bytes_per_pixel.into()) {
-            Some(offset) => offset,
-            None => return, 
-        };
-    
-        if byte_offset + self.info.bytes_per_pixel as usize > self.framebuffer.len() {
-            return; 
-        }
-    
-        let color = match self.info.pixel_format {
-            PixelFormat::Rgb => [r, g, b, 0],
-            PixelFormat::Bgr => [b, g, r, 0],
-            _ => return, 
-        };
-    
-        self.framebuffer[byte_offset..(byte_offset + self.info.bytes_per_pixel as usize)]
-            .copy_from_slice(&color[..usize::from(self.info.bytes_per_pixel)]);
-    }
+pub fn draw_paddle(writer: &mut ScreenWriter, viewport: &Viewport, pos: Vec2, color: Color) {
+    let size = Vec2::new(Fixed::from(PADDLE_WIDTH), Fixed::from(PADDLE_HEIGHT));
+    fill_field_rect(writer, viewport, pos, size, color);
 }
 
 
-pub fn draw_paddle(writer: &mut ScreenWriter, x: usize, y: usize, r: u8, g: u8, b: u8) {
-    const PADDLE_WIDTH: usize = 15;  
-    const PADDLE_HEIGHT: usize = 100; 
-
-    for dy in 0..PADDLE_HEIGHT {
-        for dx in 0..PADDLE_WIDTH {
-            writer.draw_pixel(x + dx, y + dy, r, g, b);
-        }
-    }
+pub fn draw_ball(writer: &mut ScreenWriter, viewport: &Viewport, pos: Vec2, color: Color) {
+    let size = Vec2::new(Fixed::from(BALL_SIZE), Fixed::from(BALL_SIZE));
+    fill_field_rect(writer, viewport, pos, size, color);
 }
 
 
-pub fn draw_ball(writer: &mut ScreenWriter, x: usize, y: usize, r: u8, g: u8, b: u8) {
-    const BALL_SIZE: usize = 12;     
-    for dy in 0..BALL_SIZE {  
-        for dx in 0..BALL_SIZE {
-            writer.draw_pixel(x + dx, y + dy, r, g, b);
-        }
+pub fn draw_center_line(writer: &mut ScreenWriter, viewport: &Viewport) {
+    let size = Vec2::new(Fixed::from(2), Fixed::from(10));
+    for y in (0..FIELD_HEIGHT).step_by(20) {
+        let pos = Vec2::new(Fixed::from(FIELD_WIDTH / 2 - 1), Fixed::from(y));
+        fill_field_rect(writer, viewport, pos, size, Color::rgb(200, 200, 200));
     }
 }
 
 
-pub fn draw_center_line(writer: &mut ScreenWriter) {
-    let mid_x = writer.width() / 2;
-    for y in (0..writer.height()).step_by(20) {  
-        for dy in 0..10 { 
-            writer.draw_pixel(mid_x, y + dy, 200, 200, 200);
-        }
-    }
+/// Screen pixel of a point given in whole field units.
+pub fn field_point(viewport: &Viewport, x: usize, y: usize) -> (usize, usize) {
+    let (x, y) = viewport.point(Vec2::new(Fixed::from(x), Fixed::from(y)));
+    (x.max(0) as usize, y.max(0) as usize)
 }
 
 
-pub fn draw_score(writer: &mut ScreenWriter, player1_score: usize, player2_score: usize) {
-    let mid_x = writer.width() / 2;
+pub fn draw_score(writer: &mut ScreenWriter, viewport: &Viewport, player1_score: usize, player2_score: usize) {
+    let style = TextStyle::new(Color::rgb(200, 200, 200)).scale(3).fit_to(viewport);
+    let (left_x, top) = field_point(viewport, FIELD_WIDTH / 2 - 20, 20);
+    let (right_x, _) = field_point(viewport, FIELD_WIDTH / 2 + 20, 20);
 
-    writer.write_number(player1_score, mid_x - 40, 20);  // Left player score
-    writer.write_number(player2_score, mid_x + 20, 20);  // Right player score
+    let mut score = TextBuffer::<20>::new();
+    let _ = write!(score, "{player1_score}");
+    writer.draw_text(score.as_str(), left_x, top, &style.align(HAlign::Right, VAlign::Top));  // Left player score
+    let mut score = TextBuffer::<20>::new();
+    let _ = write!(score, "{player2_score}");
+    writer.draw_text(score.as_str(), right_x, top, &style.align(HAlign::Left, VAlign::Top));  // Right player score
 }
 
 
@@ -198,54 +146,143 @@ pub fn draw_score(writer: &mut ScreenWriter, player1_score: usize, player2_score
 unsafe impl Send for ScreenWriter {}
 unsafe impl Sync for ScreenWriter {}
 
-impl fmt::Write for ScreenWriter {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            self.write_char(c);
+impl ScreenWriter {
+    /// Draws `text`, which may span several lines, anchored at `(x, y)` as `style` says, and
+    /// returns its size. Each line is aligned on its own, so centred lines stay centred.
+    pub fn draw_text(&mut self, text: &str, x: usize, y: usize, style: &TextStyle) -> TextMetrics {
+        let metrics = measure_text(text, style);
+        let top = y as isize - match style.v_align {
+            VAlign::Top => 0,
+            VAlign::Middle => metrics.height / 2,
+            VAlign::Baseline => metrics.baseline,
+        } as isize;
+
+        for (line_index, line) in text.split('\n').enumerate() {
+            let line_width = line.chars().count() * style.glyph_width();
+            let left = x as isize - match style.h_align {
+                HAlign::Left => 0,
+                HAlign::Centre => line_width / 2,
+                HAlign::Right => line_width,
+            } as isize;
+            let line_top = top + (line_index * style.line_height()) as isize;
+            // Glyphs fill their line's box exactly, so it is the damage for the whole line.
+            let line_box = self.clip_signed(left, line_top, line_width as isize, style.line_height() as isize);
+            if line_box.is_empty() {
+                continue;
+            }
+            self.damage(line_box);
+            for (column, c) in line.chars().enumerate() {
+                let Some(glyph) = get_raster(c, style.weight, text::RASTER_HEIGHT) else { continue };
+                let at = (left + (column * style.glyph_width()) as isize, line_top);
+                self.draw_mask(glyph.raster(), at, style.scale, style.color);
+            }
         }
-        Ok(())
+        metrics
     }
 }
 
-impl ScreenWriter {
-    pub fn write_number(&mut self, num: usize, x: usize, y: usize) {
-        let text = num.to_string();
-        self.x_pos = x;
-        self.y_pos = y;
-        for c in text.chars() {
-            self.write_char(c);
+/// Takes the framebuffer for the panic screen, whatever the screen writer was in the middle of.
+/// Returns None before the screen is set up.
+pub fn take_over_for_panic() -> Option<PanicScreen> {
+    // SAFETY: only the panic handler calls this, with interrupts off. The code it stopped never
+    // runs again, so nothing else uses the writer or its framebuffer any more.
+    let writer = unsafe { WRITER.get_mut() }.as_mut()?;
+    let framebuffer = unsafe { core::slice::from_raw_parts_mut(writer.framebuffer.as_mut_ptr(), writer.framebuffer.len()) };
+    Some(PanicScreen {
+        framebuffer,
+        info: writer.info,
+        encoder: writer.encoder(),
+        background: Color::BLACK,
+        style: TextStyle::new(Color::WHITE),
+        margin: 0,
+        x: 0,
+        y: 0,
+    })
+}
+
+/// Writes text straight to the framebuffer like a terminal, wrapping long lines. Unlike
+/// [ScreenWriter] it has no back buffer or damage lists, which a panic may have left half
+/// updated, and it never allocates. Text below the bottom margin is dropped.
+pub struct PanicScreen {
+    framebuffer: &'static mut [u8],
+    info: FrameBufferInfo,
+    encoder: PixelEncoder,
+    background: Color,
+    style: TextStyle,
+    margin: usize,
+    x: usize,
+    y: usize,
+}
+
+impl PanicScreen {
+    /// Fills the screen with `background` and starts writing in its top left corner, `margin`
+    /// pixels in from the edges.
+    pub fn clear(&mut self, background: Color, margin: usize) {
+        self.background = background;
+        self.margin = margin;
+        self.x = margin;
+        self.y = margin;
+        let (width, height) = (self.info.width, self.info.height);
+        self.fill(Rect::new(0, 0, width, height), background);
+    }
+
+    /// Style of the text written from now on. Only its colour, scale and weight are used.
+    pub fn set_style(&mut self, style: TextStyle) {
+        self.style = style;
+    }
+
+    fn newline(&mut self) {
+        self.x = self.margin;
+        self.y += self.style.line_height();
+    }
+
+    fn fill(&mut self, rect: Rect, color: Color) {
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        let encoded = self.encoder.encode(color.r, color.g, color.b);
+        for y in rect.y..rect.bottom() {
+            let start = (y * self.info.stride + rect.x) * bytes_per_pixel;
+            for pixel in self.framebuffer[start..start + rect.width * bytes_per_pixel].chunks_exact_mut(bytes_per_pixel) {
+                pixel.copy_from_slice(&encoded[..bytes_per_pixel]);
+            }
         }
     }
 
-    pub fn write_large_char(&mut self, c: char, x: usize, y: usize, r: u8, g: u8, b: u8) {
-        const SCALE: usize = 3; 
-        
-        if let Some(bitmap_char) = get_raster(c, FontWeight::Regular, Size16) {
-            for (char_y, row) in bitmap_char.raster().iter().enumerate() {
-                for (char_x, intensity) in row.iter().enumerate() {
-                    if *intensity > 0 {
-                        for dy in 0..SCALE {
-                            for dx in 0..SCALE {
-                                self.draw_pixel(
-                                    x + char_x * SCALE + dx,
-                                    y + char_y * SCALE + dy,
-                                    r, g, b
-                                );
-                            }
-                        }
-                    }
+    fn draw_glyph(&mut self, c: char) {
+        let glyph = get_raster(c, self.style.weight, text::RASTER_HEIGHT)
+            .or_else(|| get_raster('?', self.style.weight, text::RASTER_HEIGHT));
+        let Some(glyph) = glyph else { return };
+        let scale = self.style.scale;
+        let background = [self.background.r, self.background.g, self.background.b];
+        for (row_index, row) in glyph.raster().iter().enumerate() {
+            for (column, &intensity) in row.iter().enumerate() {
+                if intensity > 0 {
+                    let [r, g, b] = self.style.color.faded(intensity).over(background);
+                    let cell = Rect::new(self.x + column * scale, self.y + row_index * scale, scale, scale);
+                    self.fill(cell, Color::rgb(r, g, b));
                 }
             }
         }
     }
-    pub fn write_large_text(&mut self, text: &str, x: usize, y: usize, r: u8, g: u8, b: u8) {
-        const SCALE: usize = 3;
-        const CHAR_WIDTH: usize = 8 * SCALE;
-
-        let mut current_x = x;
-        for c in text.chars() {
-            self.write_large_char(c, current_x, y, r, g, b);
-            current_x += CHAR_WIDTH;
+}
+
+impl core::fmt::Write for PanicScreen {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let right = self.info.width.saturating_sub(self.margin);
+        let bottom = self.info.height.saturating_sub(self.margin);
+        for c in s.chars() {
+            if c == '\n' {
+                self.newline();
+                continue;
+            }
+            if self.x + self.style.glyph_width() > right {
+                self.newline();
+            }
+            if self.y + self.style.line_height() > bottom {
+                return Ok(());
+            }
+            self.draw_glyph(c);
+            self.x += self.style.glyph_width();
         }
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,306 @@
+// Interrupt-driven driver for the COM1 serial port (a 16550 UART). Outgoing bytes wait in a ring
+// buffer and are fed to the UART from its transmit interrupt; incoming bytes are collected by the
+// receive interrupt and handed to the `HandlerTable::serial` callback, or kept for [read_byte].
+// Until the interrupt is hooked up, and after a panic, bytes are sent by polling instead.
+
+use core::fmt;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::{Port, PortReadOnly};
+
+/// I/O port of COM1's first register.
+const COM1_BASE: u16 = 0x3F8;
+/// IRQ line COM1 is wired to.
+pub const COM1_IRQ: u8 = 4;
+/// Bytes the UART's transmit FIFO holds.
+const FIFO_SIZE: usize = 16;
+const TX_BUFFER_SIZE: usize = 4096;
+const RX_BUFFER_SIZE: usize = 256;
+
+// Interrupt enable register bits.
+pub(crate) const IER_RECEIVED: u8 = 0x01;
+const IER_TRANSMIT_EMPTY: u8 = 0x02;
+// Interrupt identification register values, in its low four bits. Bit 0 is set when nothing is
+// pending.
+const IIR_TRANSMIT_EMPTY: u8 = 0x02;
+const IIR_RECEIVED: u8 = 0x04;
+const IIR_RECEIVE_TIMEOUT: u8 = 0x0C;
+// Line status register bits.
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_TRANSMIT_EMPTY: u8 = 0x20;
+
+static COM1: Mutex<Com1> = Mutex::new(Com1::new(COM1_BASE));
+/// Set once the serial interrupt is routed and handled; until then output is polled.
+static INTERRUPT_DRIVEN: AtomicBool = AtomicBool::new(false);
+
+/// Writes to COM1. Can be used anywhere, including interrupt handlers.
+pub struct Writer;
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Programs the UART for 115200 baud, 8N1, with FIFOs and the receive interrupt on. Calling it
+/// again does nothing.
+pub fn init() {
+    without_interrupts(|| COM1.lock().init());
+}
+
+/// Queues `bytes` for sending. Only waits for the UART when the buffer is full or output is
+/// still polled.
+pub fn write_bytes(bytes: &[u8]) {
+    without_interrupts(|| {
+        let mut com1 = COM1.lock();
+        if !INTERRUPT_DRIVEN.load(Ordering::Relaxed) {
+            com1.flush();
+            for &byte in bytes {
+                com1.uart.send(byte);
+            }
+            return;
+        }
+        for &byte in bytes {
+            while com1.tx.is_full() {
+                com1.send_next();
+            }
+            com1.tx.push(byte);
+        }
+        com1.start_sending();
+    });
+}
+
+/// Takes the oldest received byte not yet handed to a callback.
+pub fn read_byte() -> Option<u8> {
+    without_interrupts(|| COM1.lock().rx.pop())
+}
+
+/// Switches to interrupt-driven output. Called once the IDT has a handler for the serial vector.
+pub(crate) fn enable_interrupts() {
+    INTERRUPT_DRIVEN.store(true, Ordering::Relaxed);
+}
+
+/// Called by the serial interrupt handler: buffers what arrived and keeps the transmitter fed.
+/// The IRQ is edge-triggered and the UART only raises it again once nothing is pending, so this
+/// keeps going until the UART says so.
+pub(crate) fn handle_interrupt() {
+    let mut com1 = COM1.lock();
+    loop {
+        match com1.uart.interrupt_id() & 0x0F {
+            IIR_RECEIVED | IIR_RECEIVE_TIMEOUT => {
+                while com1.uart.line_status() & LSR_DATA_READY != 0 {
+                    let byte = com1.uart.receive();
+                    // Input arriving faster than it is read is dropped.
+                    com1.rx.push(byte);
+                }
+            }
+            IIR_TRANSMIT_EMPTY => com1.start_sending(),
+            // Nothing pending. Line and modem status interrupts are never enabled.
+            _ => break,
+        }
+    }
+}
+
+/// Makes the port usable from a panic, whatever state it was left in: breaks the lock if the
+/// panicking code held it, sends everything still queued, and polls from then on.
+pub fn take_over_for_panic() {
+    INTERRUPT_DRIVEN.store(false, Ordering::Relaxed);
+    if COM1.is_locked() {
+        // SAFETY: interrupts are off and nothing else runs after a panic, so the holder of the
+        // lock will never touch it again.
+        unsafe { COM1.force_unlock() };
+    }
+    let mut com1 = COM1.lock();
+    com1.init();
+    com1.uart.set_interrupts(IER_RECEIVED);
+    com1.transmitting = false;
+    com1.flush();
+}
+
+struct Com1 {
+    uart: Uart,
+    tx: ByteRing<TX_BUFFER_SIZE>,
+    rx: ByteRing<RX_BUFFER_SIZE>,
+    initialized: bool,
+    /// Whether the transmit-empty interrupt is enabled.
+    transmitting: bool,
+}
+
+impl Com1 {
+    const fn new(base: u16) -> Self {
+        Self { uart: Uart::new(base), tx: ByteRing::new(), rx: ByteRing::new(), initialized: false, transmitting: false }
+    }
+
+    fn init(&mut self) {
+        if !self.initialized {
+            self.uart.init();
+            self.uart.set_interrupts(IER_RECEIVED);
+            self.initialized = true;
+        }
+    }
+
+    /// Sends the oldest queued byte, waiting for the UART if needed.
+    fn send_next(&mut self) {
+        if let Some(byte) = self.tx.pop() {
+            self.uart.send(byte);
+        }
+    }
+
+    fn flush(&mut self) {
+        while !self.tx.is_empty() {
+            self.send_next();
+        }
+    }
+
+    /// Refills the transmit FIFO if it has drained and asks for an interrupt when it drains
+    /// again, for as long as there is more to send.
+    fn start_sending(&mut self) {
+        if self.uart.line_status() & LSR_TRANSMIT_EMPTY != 0 {
+            for _ in 0..FIFO_SIZE {
+                let Some(byte) = self.tx.pop() else { break };
+                self.uart.write_data(byte);
+            }
+        }
+        let transmitting = !self.tx.is_empty();
+        if transmitting != self.transmitting {
+            let transmit = if transmitting { IER_TRANSMIT_EMPTY } else { 0 };
+            self.uart.set_interrupts(IER_RECEIVED | transmit);
+            self.transmitting = transmitting;
+        }
+    }
+}
+
+/// Registers of a 16550 UART. Also drives COM2 for the [GDB stub](crate::gdb).
+pub(crate) struct Uart {
+    data: Port<u8>,
+    interrupt_enable: Port<u8>,
+    fifo_control: Port<u8>,
+    /// Shares its port with `fifo_control`, which is write-only.
+    interrupt_id: PortReadOnly<u8>,
+    line_control: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: PortReadOnly<u8>,
+    scratch: Port<u8>,
+}
+
+impl Uart {
+    pub(crate) const fn new(base: u16) -> Self {
+        Self {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            fifo_control: Port::new(base + 2),
+            interrupt_id: PortReadOnly::new(base + 2),
+            line_control: Port::new(base + 3),
+            modem_control: Port::new(base + 4),
+            line_status: PortReadOnly::new(base + 5),
+            scratch: Port::new(base + 7),
+        }
+    }
+
+    /// Whether a UART answers at this address: its scratch register keeps what is written to it.
+    pub(crate) fn is_present(&mut self) -> bool {
+        unsafe {
+            self.scratch.write(0x5A);
+            self.scratch.read() == 0x5A
+        }
+    }
+
+    pub(crate) fn init(&mut self) {
+        unsafe {
+            self.interrupt_enable.write(0);
+            self.line_control.write(0x80); // Divisor latch access
+            self.data.write(0x01); // Divisor 1: 115200 baud
+            self.interrupt_enable.write(0x00);
+            self.line_control.write(0x03); // 8 data bits, no parity, one stop bit
+            self.fifo_control.write(0x07); // Enable and clear FIFOs, receive interrupt at every byte
+            self.modem_control.write(0x0B); // DTR, RTS, and OUT2, which gates the IRQ line
+        }
+    }
+
+    pub(crate) fn set_interrupts(&mut self, mask: u8) {
+        unsafe { self.interrupt_enable.write(mask) }
+    }
+
+    /// What the UART wants attention for, the most urgent first; reading it clears a
+    /// transmit-empty interrupt.
+    fn interrupt_id(&mut self) -> u8 {
+        unsafe { self.interrupt_id.read() }
+    }
+
+    fn line_status(&mut self) -> u8 {
+        unsafe { self.line_status.read() }
+    }
+
+    fn write_data(&mut self, byte: u8) {
+        unsafe { self.data.write(byte) }
+    }
+
+    fn receive(&mut self) -> u8 {
+        unsafe { self.data.read() }
+    }
+
+    /// Takes a received byte, if there is one.
+    pub(crate) fn try_receive(&mut self) -> Option<u8> {
+        (self.line_status() & LSR_DATA_READY != 0).then(|| self.receive())
+    }
+
+    /// Waits for the next received byte.
+    pub(crate) fn receive_polled(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_receive() {
+                return byte;
+            }
+            spin_loop();
+        }
+    }
+
+    /// Sends one byte by waiting until the UART can take it.
+    pub(crate) fn send(&mut self, byte: u8) {
+        while self.line_status() & LSR_TRANSMIT_EMPTY == 0 {
+            spin_loop();
+        }
+        self.write_data(byte);
+    }
+}
+
+/// Fixed-size FIFO of bytes.
+struct ByteRing<const N: usize> {
+    bytes: [u8; N],
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> ByteRing<N> {
+    const fn new() -> Self {
+        Self { bytes: [0; N], start: 0, len: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Appends `byte`, or drops it if the ring is full.
+    fn push(&mut self, byte: u8) {
+        if !self.is_full() {
+            self.bytes[(self.start + self.len) % N] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.bytes[self.start];
+        self.start = (self.start + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
@@ -0,0 +1,272 @@
+// Command shell on the serial port for looking inside the running kernel. The serial interrupt
+// only collects typed characters into a line; the finished command runs on the game loop between
+// two frames, so the game keeps going while the shell is used.
+
+use core::fmt::{self, Write};
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use log::info;
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::VirtAddr;
+use kernel::interrupts::{self, APICOffset};
+use kernel::{logger, serial, time};
+use pong::{GameState, Input, PongGame};
+use crate::{allocator, GAME, MATCH_CLOCK};
+
+/// Longest command line; further characters are ignored.
+const LINE_LENGTH: usize = 80;
+const PROMPT: &str = "> ";
+/// Log lines shown by `log` without an argument.
+const DEFAULT_LOG_LINES: usize = 20;
+
+const HELP: &str = "\
+help          this list
+mem           physical memory regions from the bootloader
+pt <vaddr>    walk the page tables for a virtual address (hex)
+apic          local APIC registers
+heap          kernel heap usage
+irq           interrupts taken per vector
+log [n]       last n log lines (default 20)
+score         state and score of the game
+reset         abandon the game and go back to the start screen
+pause         pause or resume the match in progress
+";
+#[cfg(feature = "fault-command")]
+const FAULT_HELP: &str = "\
+fault         crash by overflowing the kernel stack
+";
+
+/// What the commands need from the boot info.
+pub struct Machine {
+    pub memory_regions: &'static MemoryRegions,
+    pub physical_offset: u64,
+}
+
+// The memory map is never written after boot.
+unsafe impl Send for Machine {}
+unsafe impl Sync for Machine {}
+
+static MACHINE: Once<Machine> = Once::new();
+
+/// The line being typed.
+struct Editor {
+    line: [u8; LINE_LENGTH],
+    len: usize,
+    /// The line is finished and waits for [run_pending]; input is ignored until then.
+    ready: bool,
+}
+
+static EDITOR: Mutex<Editor> = Mutex::new(Editor { line: [0; LINE_LENGTH], len: 0, ready: false });
+
+/// Starts the shell and shows the first prompt.
+pub fn init(machine: Machine) {
+    MACHINE.call_once(|| machine);
+    let _ = write!(serial::Writer, "\nlab-os shell, type `help` for commands\n{PROMPT}");
+}
+
+/// Serial callback: edits the command line and echoes it. Runs in the serial interrupt.
+pub fn receive(byte: u8) {
+    let mut editor = EDITOR.lock();
+    if editor.ready {
+        return;
+    }
+    match byte {
+        b'\r' | b'\n' => {
+            serial::write_bytes(b"\n");
+            editor.ready = true;
+        }
+        // Backspace or delete: rub out the last character.
+        0x08 | 0x7F if editor.len > 0 => {
+            editor.len -= 1;
+            serial::write_bytes(b"\x08 \x08");
+        }
+        // Ctrl-C: drop the line.
+        0x03 => {
+            editor.len = 0;
+            serial::write_bytes(b"^C\n");
+            serial::write_bytes(PROMPT.as_bytes());
+        }
+        b' '..=b'~' if editor.len < LINE_LENGTH => {
+            let len = editor.len;
+            editor.line[len] = byte;
+            editor.len += 1;
+            serial::write_bytes(&[byte]);
+        }
+        _ => {}
+    }
+}
+
+/// Runs the command typed on the serial port, if one is finished. Called by the game loop.
+pub fn run_pending() {
+    let pending = without_interrupts(|| {
+        let editor = EDITOR.lock();
+        editor.ready.then_some((editor.line, editor.len))
+    });
+    let Some((line, len)) = pending else { return };
+
+    let _ = run(core::str::from_utf8(&line[..len]).unwrap_or(""), &mut serial::Writer);
+    without_interrupts(|| {
+        let mut editor = EDITOR.lock();
+        editor.len = 0;
+        editor.ready = false;
+    });
+    serial::write_bytes(PROMPT.as_bytes());
+}
+
+fn run(line: &str, out: &mut impl Write) -> fmt::Result {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else { return Ok(()) };
+    match command {
+        "help" => {
+            out.write_str(HELP)?;
+            #[cfg(feature = "fault-command")]
+            out.write_str(FAULT_HELP)?;
+            Ok(())
+        }
+        "mem" => mem(out),
+        "pt" => match words.next().and_then(parse_hex) {
+            Some(address) => page_walk(out, address),
+            None => writeln!(out, "usage: pt <vaddr>"),
+        },
+        "apic" => apic(out),
+        "heap" => heap(out),
+        "irq" => irq(out),
+        "log" => match words.next().map(str::parse) {
+            None => log(out, DEFAULT_LOG_LINES),
+            Some(Ok(lines)) => log(out, lines),
+            Some(Err(_)) => writeln!(out, "usage: log [n]"),
+        },
+        "score" => score(out),
+        "reset" => reset(out),
+        "pause" => pause(out),
+        #[cfg(feature = "fault-command")]
+        "fault" => {
+            overflow(0);
+            Ok(())
+        }
+        _ => writeln!(out, "unknown command `{command}`, try `help`"),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u64> {
+    u64::from_str_radix(text.strip_prefix("0x").unwrap_or(text), 16).ok()
+}
+
+fn machine() -> &'static Machine {
+    MACHINE.get().expect("shell is initialised before commands can arrive")
+}
+
+fn mem(out: &mut impl Write) -> fmt::Result {
+    let mut usable = 0;
+    for region in machine().memory_regions.iter() {
+        let size = region.end - region.start;
+        if region.kind == MemoryRegionKind::Usable {
+            usable += size;
+        }
+        writeln!(out, "{:#014x}-{:#014x} {:>9} KiB {:?}", region.start, region.end, size / 1024, region.kind)?;
+    }
+    writeln!(out, "{} KiB usable", usable / 1024)
+}
+
+/// Follows `address` through the four levels of the active page tables, reading each table
+/// through the bootloader's mapping of physical memory.
+fn page_walk(out: &mut impl Write, address: u64) -> fmt::Result {
+    let Ok(address) = VirtAddr::try_new(address) else {
+        return writeln!(out, "{address:#x} is not a canonical address");
+    };
+    let physical_offset = machine().physical_offset;
+    let indices = [address.p4_index(), address.p3_index(), address.p2_index(), address.p1_index()];
+    let mut table_address = Cr3::read().0.start_address().as_u64();
+
+    for (level, index) in (1..=4).rev().zip(indices) {
+        // SAFETY: every level's table is a page of physical memory, which the bootloader maps
+        // at `physical_offset`. The tables are only read.
+        let table = unsafe { &*((table_address + physical_offset) as *const PageTable) };
+        let entry = &table[index];
+        let flags = entry.flags();
+        writeln!(out, "P{level}[{:>3}] {:#014x} {flags:?}", u16::from(index), entry.addr().as_u64())?;
+
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return writeln!(out, "not mapped");
+        }
+        if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            let page_size = 4096_u64 << (9 * (level - 1));
+            let physical = entry.addr().as_u64() + (address.as_u64() & (page_size - 1));
+            return writeln!(out, "{:#x} -> {physical:#x} ({} KiB page)", address.as_u64(), page_size / 1024);
+        }
+        table_address = entry.addr().as_u64();
+    }
+    Ok(())
+}
+
+fn apic(out: &mut impl Write) -> fmt::Result {
+    for register in APICOffset::READABLE {
+        writeln!(out, "{:#05x} {:#010x} {register:?}", register as isize, interrupts::read_lapic(register))?;
+    }
+    Ok(())
+}
+
+fn heap(out: &mut impl Write) -> fmt::Result {
+    let (used, size) = allocator::usage();
+    writeln!(out, "{} KiB of {} KiB used ({}%), nothing is freed", used / 1024, size / 1024, used * 100 / size.max(1))
+}
+
+fn irq(out: &mut impl Write) -> fmt::Result {
+    for (vector, name, count) in interrupts::interrupt_counts() {
+        writeln!(out, "{vector:#04x} {name:<8} {count}")?;
+    }
+    writeln!(out, "uptime {:?}", time::uptime())
+}
+
+fn log(out: &mut impl Write, lines: usize) -> fmt::Result {
+    logger::write_last(out, lines)
+}
+
+fn score(out: &mut impl Write) -> fmt::Result {
+    let Some(game) = without_interrupts(|| GAME.lock().clone()) else { return Ok(()) };
+    let paused = if game.paused { ", paused" } else { "" };
+    writeln!(out, "{:?}{paused}: {} - {}", game.state, game.player1_score, game.player2_score)?;
+    writeln!(out, "{:?}, computer {}", game.mode, game.difficulty.name())
+}
+
+/// Drops the game back to the start screen, keeping the chosen mode and difficulty.
+fn reset(out: &mut impl Write) -> fmt::Result {
+    without_interrupts(|| {
+        let mut game = GAME.lock();
+        let Some(game) = game.as_mut() else { return };
+        let mut fresh = PongGame::new(game.field_width, game.field_height);
+        fresh.mode = game.mode;
+        fresh.difficulty = game.difficulty;
+        fresh.seed = game.seed;
+        *game = fresh;
+        MATCH_CLOCK.lock().played = time::Duration::ZERO;
+    });
+    info!("Game reset from the shell");
+    writeln!(out, "back to the start screen")
+}
+
+fn pause(out: &mut impl Write) -> fmt::Result {
+    let game = without_interrupts(|| {
+        let mut game = GAME.lock();
+        let game = game.as_mut()?;
+        game.apply_input(Input::TogglePause);
+        Some(game.clone())
+    });
+    match game {
+        Some(game) if game.state == GameState::Playing => writeln!(out, "{}", if game.paused { "paused" } else { "resumed" }),
+        _ => writeln!(out, "no match in progress"),
+    }
+}
+
+/// Recurses with a large frame until the stack runs into its guard page.
+#[cfg(feature = "fault-command")]
+#[inline(never)]
+fn overflow(depth: u64) -> u64 {
+    let frame = core::hint::black_box([depth; 64]);
+    if depth == u64::MAX {
+        return frame[0];
+    }
+    overflow(depth + 1).wrapping_add(frame[1])
+}
@@ -0,0 +1,134 @@
+// Text layout on top of the pre-rasterised Noto Sans Mono glyphs: styles, measuring and alignment.
+// Drawing lives in `ScreenWriter::draw_text`.
+
+use core::fmt;
+use noto_sans_mono_bitmap::{get_raster, get_raster_width, RasterHeight};
+use pong::Viewport;
+use gfx::Color;
+
+pub use noto_sans_mono_bitmap::FontWeight;
+
+/// Glyph size everything is scaled up from.
+pub const RASTER_HEIGHT: RasterHeight = RasterHeight::Size16;
+
+/// Which part of each line sits on the anchor's x coordinate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HAlign {
+    Left,
+    Centre,
+    Right,
+}
+
+/// Which part of the text block sits on the anchor's y coordinate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VAlign {
+    Top,
+    Middle,
+    /// The baseline of the first line.
+    Baseline,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TextStyle {
+    pub color: Color,
+    /// Whole-number magnification of the 16px glyphs; bitmaps do not scale down.
+    pub scale: usize,
+    pub weight: FontWeight,
+    pub h_align: HAlign,
+    pub v_align: VAlign,
+}
+
+impl TextStyle {
+    /// Regular weight at 16px, anchored at the top left.
+    pub const fn new(color: Color) -> Self {
+        Self { color, scale: 1, weight: FontWeight::Regular, h_align: HAlign::Left, v_align: VAlign::Top }
+    }
+
+    pub const fn scale(self, scale: usize) -> Self {
+        Self { scale: if scale == 0 { 1 } else { scale }, ..self }
+    }
+
+    pub const fn weight(self, weight: FontWeight) -> Self {
+        Self { weight, ..self }
+    }
+
+    pub const fn align(self, h_align: HAlign, v_align: VAlign) -> Self {
+        Self { h_align, v_align, ..self }
+    }
+
+    /// The style with its scale multiplied by the viewport's, rounded to the nearest whole size,
+    /// so text keeps roughly the same size relative to the field.
+    pub fn fit_to(self, viewport: &Viewport) -> Self {
+        let scale = (viewport.scale() * self.scale as i32).round();
+        self.scale(scale.max(1) as usize)
+    }
+
+    /// Advance from one character to the next; the font is monospaced.
+    pub fn glyph_width(&self) -> usize {
+        get_raster_width(self.weight, RASTER_HEIGHT) * self.scale
+    }
+
+    pub fn line_height(&self) -> usize {
+        RASTER_HEIGHT.val() * self.scale
+    }
+
+    /// Distance from the top of a line to its baseline. The glyphs carry no metrics, so this is
+    /// where the ink of a flat-bottomed capital ends.
+    pub fn baseline(&self) -> usize {
+        let rows = get_raster('H', self.weight, RASTER_HEIGHT)
+            .and_then(|glyph| glyph.raster().iter().rposition(|row| row.iter().any(|&ink| ink > 127)))
+            .map_or(RASTER_HEIGHT.val(), |last_row| last_row + 1);
+        rows * self.scale
+    }
+}
+
+/// Size of a block of text as it would be drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextMetrics {
+    /// Width of the longest line.
+    pub width: usize,
+    pub height: usize,
+    pub lines: usize,
+    /// Distance from the top of the block to the baseline of its first line.
+    pub baseline: usize,
+}
+
+/// Measures `text`, which may span several lines separated by `\n`.
+pub fn measure_text(text: &str, style: &TextStyle) -> TextMetrics {
+    let lines = text.split('\n').count();
+    let longest = text.split('\n').map(|line| line.chars().count()).max().unwrap_or(0);
+    TextMetrics {
+        width: longest * style.glyph_width(),
+        height: lines * style.line_height(),
+        lines,
+        baseline: style.baseline(),
+    }
+}
+
+/// Fixed-size buffer for formatting short on-screen text without allocating every frame.
+pub struct TextBuffer<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> TextBuffer<N> {
+    pub fn new() -> Self {
+        Self { bytes: [0; N], len: 0 }
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl<const N: usize> fmt::Write for TextBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > N {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
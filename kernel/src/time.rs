@@ -0,0 +1,132 @@
+// Monotonic kernel clock based on the time stamp counter. The TSC frequency is measured against
+// the PIT while the LAPIC timer is calibrated at boot (see `interrupts::init_timer`).
+
+use core::arch::x86_64::_rdtsc;
+use core::hint::spin_loop;
+use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicU64, Ordering};
+pub use core::time::Duration;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// TSC increments per second, or 0 before calibration.
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// TSC value at calibration, which the clock treats as boot time.
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+/// Number of timer interrupts taken so far.
+static TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// A point in time since boot. Only ever moves forward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Instant(read_tsc())
+    }
+
+    /// Time since boot.
+    pub fn since_boot(&self) -> Duration {
+        self.duration_since(Instant(BOOT_TSC.load(Ordering::Relaxed)))
+    }
+
+    /// Time between `earlier` and `self`, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        ticks_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_add(duration_to_ticks(rhs)))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_sub(duration_to_ticks(rhs)))
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// Current time on the monotonic clock.
+pub fn now() -> Instant {
+    Instant::now()
+}
+
+/// Time since boot.
+pub fn uptime() -> Duration {
+    Instant::now().since_boot()
+}
+
+/// Number of timer interrupts taken since they were enabled.
+pub fn timer_ticks() -> u64 {
+    TIMER_TICKS.load(Ordering::Relaxed)
+}
+
+/// TSC increments per second, as measured at boot.
+pub fn tsc_frequency() -> u64 {
+    TSC_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Waits for `duration`, halting the CPU between interrupts while interrupts are enabled.
+pub fn sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        if x86_64::instructions::interrupts::are_enabled() {
+            x86_64::instructions::hlt();
+        } else {
+            spin_loop();
+        }
+    }
+}
+
+/// Waits for `duration` without halting, e.g. for short hardware delays.
+pub fn busy_sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        spin_loop();
+    }
+}
+
+pub(crate) fn set_tsc_frequency(frequency: u64, boot_tsc: u64) {
+    BOOT_TSC.store(boot_tsc, Ordering::Relaxed);
+    TSC_FREQUENCY.store(frequency, Ordering::Relaxed);
+}
+
+pub(crate) fn record_timer_tick() {
+    TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn read_tsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let frequency = tsc_frequency().max(1) as u128;
+    let nanos = ticks as u128 * NANOS_PER_SEC / frequency;
+    Duration::new((nanos / NANOS_PER_SEC) as u64, (nanos % NANOS_PER_SEC) as u32)
+}
+
+fn duration_to_ticks(duration: Duration) -> u64 {
+    let ticks = duration.as_nanos() * tsc_frequency() as u128 / NANOS_PER_SEC;
+    ticks.min(u64::MAX as u128) as u64
+}
@@ -0,0 +1,100 @@
+// Entry code for interrupts whose handlers need every register of the interrupted code. The
+// `x86-interrupt` calling convention only hands over the `InterruptStackFrame`, so these entries
+// push the general purpose registers next to it, call the handler with the whole frame, and
+// restore the registers from the frame, which the handler may have changed, before `iretq`.
+
+use core::fmt;
+
+/// Registers of the interrupted code, in the order the entry code leaves them on the stack: the
+/// general purpose registers and the vector it pushes, then what the CPU pushed when taking the
+/// interrupt.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    /// The CPU's error code for exceptions that have one, otherwise 0.
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// A register dump, three registers to a line.
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let registers = [
+            ("rip", self.rip), ("rsp", self.rsp), ("rflags", self.rflags),
+            ("rax", self.rax), ("rbx", self.rbx), ("rcx", self.rcx),
+            ("rdx", self.rdx), ("rsi", self.rsi), ("rdi", self.rdi),
+            ("rbp", self.rbp), ("r8", self.r8), ("r9", self.r9),
+            ("r10", self.r10), ("r11", self.r11), ("r12", self.r12),
+            ("r13", self.r13), ("r14", self.r14), ("r15", self.r15),
+        ];
+        for line in registers.chunks(3) {
+            for (column, (name, value)) in line.iter().enumerate() {
+                let separator = if column == 0 { "" } else { "  " };
+                write!(f, "{separator}{name:<6} {value:#018x}")?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "cs     {:#06x}  ss     {:#06x}", self.cs, self.ss)
+    }
+}
+
+/// Defines `$entry`, an IDT entry point for `$vector` that calls `$handler`, an
+/// `extern "C" fn(&mut TrapFrame)`, and then resumes the interrupted code. Add `error_code` for
+/// the exceptions where the CPU pushes one; for the others a 0 takes its place.
+macro_rules! trap_entry {
+    ($entry:ident, $vector:expr => $handler:path) => {
+        $crate::trap::trap_entry!(@define $entry, $vector, $handler, "push 0");
+    };
+    ($entry:ident, $vector:expr, error_code => $handler:path) => {
+        $crate::trap::trap_entry!(@define $entry, $vector, $handler, "");
+    };
+    (@define $entry:ident, $vector:expr, $handler:path, $push_error_code:literal) => {
+        core::arch::global_asm!(
+            concat!(".global ", stringify!($entry)),
+            concat!(stringify!($entry), ":"),
+            $push_error_code,
+            "push {vector}",
+            "push rax", "push rbx", "push rcx", "push rdx", "push rsi", "push rdi", "push rbp",
+            "push r8", "push r9", "push r10", "push r11", "push r12", "push r13", "push r14", "push r15",
+            // The CPU aligned the stack to 16 bytes before pushing its 5 words, so after the
+            // error code, the vector and the 15 registers above it is aligned again, as the call
+            // needs.
+            "mov rdi, rsp",
+            "cld",
+            "call {handler}",
+            "pop r15", "pop r14", "pop r13", "pop r12", "pop r11", "pop r10", "pop r9", "pop r8",
+            "pop rbp", "pop rdi", "pop rsi", "pop rdx", "pop rcx", "pop rbx", "pop rax",
+            // Drop the vector and the error code.
+            "add rsp, 16",
+            "iretq",
+            vector = const $vector,
+            handler = sym $handler,
+        );
+
+        unsafe extern "C" {
+            fn $entry();
+        }
+    };
+}
+
+pub(crate) use trap_entry;
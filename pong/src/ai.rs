@@ -0,0 +1,207 @@
+// Computer-controlled paddle. The controller only looks at the public game state and moves its
+// paddle through `PongGame::move_paddle`, the same way a human would through `Input`.
+
+use crate::fixed::Fixed;
+use crate::game::{BALL_SIZE, PADDLE_WIDTH, Player, PongGame};
+use crate::rng::XorShift32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    /// The next level, wrapping from `Hard` back to `Easy`.
+    pub fn next(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy,
+        }
+    }
+
+    /// Ticks between two looks at the ball.
+    pub fn reaction_ticks(self) -> u32 {
+        match self {
+            Difficulty::Easy => 12,
+            Difficulty::Normal => 6,
+            Difficulty::Hard => 2,
+        }
+    }
+
+    /// Field units the paddle may travel in one tick.
+    pub fn max_speed(self) -> Fixed {
+        match self {
+            Difficulty::Easy => Fixed::from_int(6),
+            Difficulty::Normal => Fixed::from_int(9),
+            Difficulty::Hard => Fixed::from_int(14),
+        }
+    }
+
+    /// Largest distance, in field units, between where the ball will arrive and where the paddle aims.
+    pub fn aim_error(self) -> u32 {
+        match self {
+            Difficulty::Easy => 60,
+            Difficulty::Normal => 30,
+            Difficulty::Hard => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AiController {
+    player: Player,
+    difficulty: Difficulty,
+    rng: XorShift32,
+    reaction: u32,
+    target_y: Fixed,
+}
+
+impl AiController {
+    pub fn new(player: Player, difficulty: Difficulty, seed: u32) -> Self {
+        Self {
+            player,
+            difficulty,
+            rng: XorShift32::new(seed),
+            reaction: 0,
+            target_y: Fixed::ZERO,
+        }
+    }
+
+    pub fn player(&self) -> Player {
+        self.player
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    /// Moves the controlled paddle for one timer tick.
+    pub fn drive(&mut self, game: &mut PongGame) {
+        if self.reaction == 0 {
+            self.target_y = self.choose_target(game);
+            self.reaction = self.difficulty.reaction_ticks();
+        } else {
+            self.reaction -= 1;
+        }
+
+        let max_speed = self.difficulty.max_speed();
+        let dy = (self.target_y - game.paddle(self.player).centre_y()).clamp(-max_speed, max_speed);
+        game.move_paddle(self.player, dy);
+    }
+
+    /// Where the centre of the paddle should go: the predicted arrival point of an incoming
+    /// ball, give or take the aim error, or back to the middle while the ball moves away.
+    fn choose_target(&mut self, game: &PongGame) -> Fixed {
+        let Some(arrival) = predict_arrival_y(game, self.player) else {
+            return Fixed::from(game.field_height / 2);
+        };
+        let offset = Fixed::from_int(self.rng.symmetric(self.difficulty.aim_error()));
+        arrival + Fixed::from(BALL_SIZE / 2) + offset
+    }
+}
+
+/// Predicts the top of the ball when it reaches the face of `player`'s paddle, following its
+/// bounces off the top and bottom walls. Returns `None` while the ball moves away.
+pub fn predict_arrival_y(game: &PongGame, player: Player) -> Option<Fixed> {
+    let ball = &game.ball;
+    let (face_x, approaching) = match player {
+        Player::One => (game.player1.x + Fixed::from(PADDLE_WIDTH), ball.vel.x.is_negative()),
+        Player::Two => (game.player2.x - Fixed::from(BALL_SIZE), ball.vel.x > Fixed::ZERO),
+    };
+    if !approaching {
+        return None;
+    }
+
+    let ticks = ((face_x - ball.pos.x) / ball.vel.x).max(Fixed::ZERO);
+    let y = ball.pos.y + ball.vel.y * ticks;
+
+    // Unfold the wall bounces: the ball travels in a triangle wave between 0 and `range`.
+    let range = Fixed::from(game.field_height.saturating_sub(BALL_SIZE));
+    if range == Fixed::ZERO {
+        return Some(Fixed::ZERO);
+    }
+    let folded = Fixed::from_raw(y.raw().rem_euclid(range.raw() * 2));
+    Some(if folded > range { range * 2 - folded } else { folded })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixed::Vec2;
+    use crate::game::{GameState, Input, Mode};
+
+    fn single_player(difficulty: Difficulty) -> PongGame {
+        let mut game = PongGame::new(1280, 800);
+        game.apply_input(Input::SelectMode(Mode::SinglePlayer));
+        game.difficulty = difficulty;
+        game.apply_input(Input::Start);
+        game
+    }
+
+    #[test]
+    fn difficulty_cycles_through_all_levels() {
+        assert_eq!(Difficulty::Easy.next(), Difficulty::Normal);
+        assert_eq!(Difficulty::Normal.next(), Difficulty::Hard);
+        assert_eq!(Difficulty::Hard.next(), Difficulty::Easy);
+    }
+
+    #[test]
+    fn harder_levels_react_faster_move_faster_and_aim_better() {
+        let levels = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+        for pair in levels.windows(2) {
+            assert!(pair[0].reaction_ticks() > pair[1].reaction_ticks());
+            assert!(pair[0].max_speed() < pair[1].max_speed());
+            assert!(pair[0].aim_error() > pair[1].aim_error());
+        }
+    }
+
+    #[test]
+    fn prediction_follows_wall_bounces() {
+        let mut game = single_player(Difficulty::Hard);
+        game.ball.pos = Vec2::new(game.player2.x - Fixed::from_int(BALL_SIZE as i32 + 100), Fixed::from_int(30));
+        game.ball.vel = Vec2::new(Fixed::from_int(10), Fixed::from_int(-10));
+
+        // Ten ticks up from y = 30 hits the top wall after three and comes back down to 70.
+        assert_eq!(predict_arrival_y(&game, Player::Two), Some(Fixed::from_int(70)));
+        assert_eq!(predict_arrival_y(&game, Player::One), None);
+    }
+
+    #[test]
+    fn paddle_speed_is_capped_by_difficulty() {
+        for difficulty in [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard] {
+            let mut game = single_player(difficulty);
+            game.player2.y = Fixed::ZERO;
+            game.ball.pos = Vec2::new(
+                Fixed::from(game.field_width / 2),
+                Fixed::from(game.field_height - BALL_SIZE - 20),
+            );
+            game.ball.vel = Vec2::new(Fixed::from_int(10), Fixed::ZERO);
+
+            let mut ai = AiController::new(Player::Two, difficulty, 1);
+            ai.drive(&mut game);
+            assert_eq!(game.player2.y, difficulty.max_speed());
+        }
+    }
+
+    #[test]
+    fn computer_returns_the_ball_on_hard() {
+        let mut game = single_player(Difficulty::Hard);
+        for _ in 0..5000 {
+            game.step();
+        }
+        // The idle human on the left loses every point, the computer none.
+        assert_eq!(game.player1_score, 0);
+        assert_eq!(game.state, GameState::GameOver);
+    }
+}
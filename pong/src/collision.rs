@@ -0,0 +1,161 @@
+// Swept axis-aligned bounding box tests. Instead of checking where the ball ends up after a tick,
+// the ball's whole path during the tick is tested, so a fast ball cannot skip over a thin paddle.
+
+use crate::fixed::{Fixed, Vec2};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Aabb {
+    /// Top left corner.
+    pub pos: Vec2,
+    pub size: Vec2,
+}
+
+impl Aabb {
+    pub fn new(x: Fixed, y: Fixed, width: Fixed, height: Fixed) -> Self {
+        Self { pos: Vec2::new(x, y), size: Vec2::new(width, height) }
+    }
+
+    pub fn right(&self) -> Fixed {
+        self.pos.x + self.size.x
+    }
+
+    pub fn bottom(&self) -> Fixed {
+        self.pos.y + self.size.y
+    }
+
+    /// True if the boxes share some area; touching edges do not count.
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.pos.x < other.right()
+            && other.pos.x < self.right()
+            && self.pos.y < other.bottom()
+            && other.pos.y < self.bottom()
+    }
+}
+
+/// First contact of a moving box with a still one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit {
+    /// Share of the movement done before contact, from 0 to 1.
+    pub time: Fixed,
+    /// Unit vector pointing out of the face that was hit, e.g. `(1, 0)` for a right face.
+    pub normal: Vec2,
+}
+
+/// Sweeps `moving` along `delta` and returns where it first touches `target`.
+///
+/// Boxes that already overlap at the start, or only brush past each other along an edge, do
+/// not count as a hit, so that a box resting against a surface can always move away from it.
+pub fn sweep(moving: &Aabb, delta: Vec2, target: &Aabb) -> Option<Hit> {
+    let (entry_x, exit_x) = axis_times(moving.pos.x, moving.right(), target.pos.x, target.right(), delta.x)?;
+    let (entry_y, exit_y) = axis_times(moving.pos.y, moving.bottom(), target.pos.y, target.bottom(), delta.y)?;
+
+    let entry = entry_x.max(entry_y);
+    let exit = exit_x.min(exit_y);
+    if entry >= exit || entry < Fixed::ZERO || entry > Fixed::ONE {
+        return None;
+    }
+
+    let normal = if entry_x > entry_y {
+        Vec2::new(-Fixed::ONE * delta.x.raw().signum(), Fixed::ZERO)
+    } else {
+        Vec2::new(Fixed::ZERO, -Fixed::ONE * delta.y.raw().signum())
+    };
+    Some(Hit { time: entry, normal })
+}
+
+/// Times, as shares of `delta`, at which the span `start..end` starts and stops overlapping
+/// `target_start..target_end`. `None` if the spans never overlap along this axis.
+fn axis_times(
+    start: Fixed,
+    end: Fixed,
+    target_start: Fixed,
+    target_end: Fixed,
+    delta: Fixed,
+) -> Option<(Fixed, Fixed)> {
+    if delta == Fixed::ZERO {
+        return (start < target_end && target_start < end).then_some((Fixed::MIN, Fixed::MAX));
+    }
+    let (entry_distance, exit_distance) = if delta > Fixed::ZERO {
+        (target_start - end, target_end - start)
+    } else {
+        (target_end - start, target_start - end)
+    };
+    Some((entry_distance.saturating_div(delta), exit_distance.saturating_div(delta)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(value: i32) -> Fixed {
+        Fixed::from_int(value)
+    }
+
+    fn square(x: i32, y: i32, size: i32) -> Aabb {
+        Aabb::new(px(x), px(y), px(size), px(size))
+    }
+
+    #[test]
+    fn reports_contact_time_and_normal() {
+        let ball = square(0, 0, 10);
+        let wall = Aabb::new(px(30), px(-50), px(10), px(100));
+
+        let hit = sweep(&ball, Vec2::new(px(40), px(0)), &wall).unwrap();
+        assert_eq!(hit.time, Fixed::from_ratio(1, 2));
+        assert_eq!(hit.normal, Vec2::new(-Fixed::ONE, Fixed::ZERO));
+
+        let hit = sweep(&square(50, 0, 10), Vec2::new(px(-20), px(0)), &wall).unwrap();
+        assert_eq!(hit.time, Fixed::from_ratio(1, 2));
+        assert_eq!(hit.normal, Vec2::new(Fixed::ONE, Fixed::ZERO));
+    }
+
+    #[test]
+    fn vertical_faces_have_vertical_normals() {
+        let floor = Aabb::new(px(-100), px(100), px(200), px(50));
+        let hit = sweep(&square(0, 80, 10), Vec2::new(px(5), px(20)), &floor).unwrap();
+        assert_eq!(hit.time, Fixed::from_ratio(1, 2));
+        assert_eq!(hit.normal, Vec2::new(Fixed::ZERO, -Fixed::ONE));
+    }
+
+    #[test]
+    fn fast_box_cannot_tunnel_through_thin_target() {
+        let paddle = Aabb::new(px(100), px(0), px(2), px(100));
+        let ball = square(0, 40, 10);
+        let delta = Vec2::new(px(500), px(0));
+
+        // Neither the start nor the end position overlaps the paddle...
+        let end = Aabb { pos: Vec2::new(px(500), px(40)), ..ball };
+        assert!(!ball.overlaps(&paddle) && !end.overlaps(&paddle));
+        // ...but the path between them does.
+        let hit = sweep(&ball, delta, &paddle).unwrap();
+        assert_eq!(hit.time, Fixed::from_ratio(90, 500));
+    }
+
+    #[test]
+    fn misses_are_not_hits() {
+        let target = square(100, 100, 10);
+        // Too short.
+        assert_eq!(sweep(&square(0, 100, 10), Vec2::new(px(50), px(0)), &target), None);
+        // Passes above.
+        assert_eq!(sweep(&square(0, 0, 10), Vec2::new(px(200), px(50)), &target), None);
+        // Moving away.
+        assert_eq!(sweep(&square(0, 100, 10), Vec2::new(px(-50), px(0)), &target), None);
+        // Not moving at all.
+        assert_eq!(sweep(&square(0, 100, 10), Vec2::ZERO, &target), None);
+    }
+
+    #[test]
+    fn resting_contact_can_move_away_but_not_in() {
+        let wall = Aabb::new(px(10), px(0), px(10), px(100));
+        let ball = square(0, 50, 10);
+        assert_eq!(sweep(&ball, Vec2::new(px(-5), px(0)), &wall), None);
+        assert_eq!(sweep(&ball, Vec2::new(px(5), px(0)), &wall).map(|hit| hit.time), Some(Fixed::ZERO));
+    }
+
+    #[test]
+    fn works_with_negative_coordinates() {
+        let wall = Aabb::new(px(-1000), px(-500), px(100), px(1000));
+        let hit = sweep(&square(-800, -10, 10), Vec2::new(px(-200), px(0)), &wall).unwrap();
+        assert_eq!(hit.time, Fixed::from_ratio(1, 2));
+    }
+}
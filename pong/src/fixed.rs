@@ -0,0 +1,235 @@
+// Signed 16.16 fixed-point numbers. The game runs without an FPU dependency and stays
+// bit-for-bit reproducible between the kernel and the host tests.
+
+use core::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i32);
+
+impl Fixed {
+    pub const FRAC_BITS: u32 = 16;
+    pub const ZERO: Fixed = Fixed(0);
+    pub const ONE: Fixed = Fixed(1 << Self::FRAC_BITS);
+    pub const MIN: Fixed = Fixed(i32::MIN);
+    pub const MAX: Fixed = Fixed(i32::MAX);
+
+    pub const fn from_int(value: i32) -> Self {
+        Fixed(value << Self::FRAC_BITS)
+    }
+
+    /// `num / den` rounded towards zero, e.g. `Fixed::from_ratio(1, 2)` is one half.
+    pub const fn from_ratio(num: i32, den: i32) -> Self {
+        Fixed((((num as i64) << Self::FRAC_BITS) / den as i64) as i32)
+    }
+
+    pub const fn from_raw(raw: i32) -> Self {
+        Fixed(raw)
+    }
+
+    pub const fn raw(self) -> i32 {
+        self.0
+    }
+
+    /// Largest integer not above `self`.
+    pub const fn floor(self) -> i32 {
+        self.0 >> Self::FRAC_BITS
+    }
+
+    /// Nearest integer, halves rounded up.
+    pub const fn round(self) -> i32 {
+        (self.0 + (1 << (Self::FRAC_BITS - 1))) >> Self::FRAC_BITS
+    }
+
+    pub const fn abs(self) -> Self {
+        Fixed(self.0.abs())
+    }
+
+    pub const fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// `self / rhs`, clamped to `MIN..=MAX` instead of overflowing when `rhs` is tiny.
+    /// Dividing by zero gives `MAX` or `MIN` according to the sign of `self`.
+    pub fn saturating_div(self, rhs: Fixed) -> Self {
+        let num = (self.0 as i64) << Self::FRAC_BITS;
+        let quotient = match rhs.0 {
+            0 if self.0 < 0 => i64::MIN,
+            0 => i64::MAX,
+            den => num / den as i64,
+        };
+        Fixed(quotient.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+
+    /// Square root, or zero for negative values.
+    pub fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Fixed::ZERO;
+        }
+        Fixed(isqrt((self.0 as u64) << Self::FRAC_BITS) as i32)
+    }
+}
+
+impl From<i32> for Fixed {
+    fn from(value: i32) -> Self {
+        Fixed::from_int(value)
+    }
+}
+
+impl From<usize> for Fixed {
+    fn from(value: usize) -> Self {
+        Fixed::from_int(value as i32)
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+    fn add(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Fixed) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+    fn sub(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, rhs: Fixed) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+    fn mul(self, rhs: Fixed) -> Fixed {
+        Fixed(((self.0 as i64 * rhs.0 as i64) >> Self::FRAC_BITS) as i32)
+    }
+}
+
+impl Mul<i32> for Fixed {
+    type Output = Fixed;
+    fn mul(self, rhs: i32) -> Fixed {
+        Fixed(self.0 * rhs)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Fixed;
+    fn div(self, rhs: Fixed) -> Fixed {
+        Fixed((((self.0 as i64) << Self::FRAC_BITS) / rhs.0 as i64) as i32)
+    }
+}
+
+impl Div<i32> for Fixed {
+    type Output = Fixed;
+    fn div(self, rhs: i32) -> Fixed {
+        Fixed(self.0 / rhs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Vec2 {
+    pub x: Fixed,
+    pub y: Fixed,
+}
+
+impl Vec2 {
+    pub const ZERO: Vec2 = Vec2 { x: Fixed::ZERO, y: Fixed::ZERO };
+
+    pub const fn new(x: Fixed, y: Fixed) -> Self {
+        Vec2 { x, y }
+    }
+
+    pub fn length(self) -> Fixed {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+}
+
+impl Add for Vec2 {
+    type Output = Vec2;
+    fn add(self, rhs: Vec2) -> Vec2 {
+        Vec2::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl AddAssign for Vec2 {
+    fn add_assign(&mut self, rhs: Vec2) {
+        self.x += rhs.x;
+        self.y += rhs.y;
+    }
+}
+
+impl Mul<Fixed> for Vec2 {
+    type Output = Vec2;
+    fn mul(self, rhs: Fixed) -> Vec2 {
+        Vec2::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+fn isqrt(n: u64) -> u64 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = n;
+    let mut y = x.div_ceil(2);
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic_keeps_fractions() {
+        let half = Fixed::from_ratio(1, 2);
+        assert_eq!(half + half, Fixed::ONE);
+        assert_eq!(Fixed::from_int(3) * half, Fixed::from_ratio(3, 2));
+        assert_eq!(Fixed::from_int(3) / Fixed::from_int(2), Fixed::from_ratio(3, 2));
+        assert_eq!(-half * 4, Fixed::from_int(-2));
+    }
+
+    #[test]
+    fn floor_and_round() {
+        assert_eq!(Fixed::from_ratio(7, 2).floor(), 3);
+        assert_eq!(Fixed::from_ratio(7, 2).round(), 4);
+        assert_eq!(Fixed::from_ratio(-1, 4).floor(), -1);
+        assert_eq!(Fixed::from_ratio(-1, 4).round(), 0);
+    }
+
+    #[test]
+    fn saturating_division() {
+        let tiny = Fixed::from_raw(1);
+        assert_eq!(Fixed::from_int(1000).saturating_div(tiny), Fixed::MAX);
+        assert_eq!(Fixed::from_int(-1000).saturating_div(tiny), Fixed::MIN);
+        assert_eq!(Fixed::ONE.saturating_div(Fixed::ZERO), Fixed::MAX);
+        assert_eq!(Fixed::from_int(3).saturating_div(Fixed::from_int(-2)), Fixed::from_ratio(-3, 2));
+    }
+
+    #[test]
+    fn square_root() {
+        assert_eq!(Fixed::from_int(144).sqrt(), Fixed::from_int(12));
+        assert_eq!(Fixed::from_int(-4).sqrt(), Fixed::ZERO);
+        let two = Fixed::from_int(2).sqrt();
+        assert!((two * two - Fixed::from_int(2)).abs() < Fixed::from_ratio(1, 1000));
+        assert_eq!(Vec2::new(Fixed::from_int(3), Fixed::from_int(4)).length(), Fixed::from_int(5));
+    }
+}
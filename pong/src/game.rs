@@ -0,0 +1,740 @@
+// Pong game rules, kept free of any framebuffer or interrupt code so that the state can be
+// snapshotted, run several times side by side and tested on the host.
+
+use crate::ai::{AiController, Difficulty};
+use crate::collision::{Aabb, sweep};
+use crate::fixed::{Fixed, Vec2};
+use crate::rng::XorShift32;
+
+/// Size of the playfield. All positions, sizes and speeds are in these virtual units, and the
+/// screen maps them to pixels through a `Viewport`, so a match plays the same at any resolution.
+pub const FIELD_WIDTH: usize = 1000;
+pub const FIELD_HEIGHT: usize = 750;
+
+pub const PADDLE_WIDTH: usize = 15;
+pub const PADDLE_HEIGHT: usize = 100;
+pub const BALL_SIZE: usize = 12;
+/// Field units a paddle travels per tick while its key is held.
+pub const PADDLE_SPEED: usize = 12;
+pub const PADDLE_MARGIN: usize = 30;
+pub const WINNING_SCORE: usize = 5;
+
+/// Ball speed, in field units per tick, at the start of every rally.
+pub const SERVE_SPEED: Fixed = Fixed::from_int(12);
+/// Speed added every time a paddle returns the ball.
+pub const RALLY_SPEED_UP: Fixed = Fixed::from_ratio(1, 2);
+pub const MAX_BALL_SPEED: Fixed = Fixed::from_int(24);
+/// Share of the paddle's own movement during the last tick passed on to the ball as spin.
+pub const SPIN_FACTOR: Fixed = Fixed::from_ratio(1, 4);
+
+/// Sine of the steepest bounce (60 degrees), reached when the ball hits the tip of a paddle.
+const MAX_BOUNCE_SIN: Fixed = Fixed::from_ratio(866, 1000);
+/// Sine of the steepest serve (30 degrees).
+const MAX_SERVE_SIN: Fixed = Fixed::from_ratio(1, 2);
+
+/// Upper bound on collisions handled in a single tick, e.g. a wall and then a paddle.
+const MAX_BOUNCES_PER_TICK: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameState {
+    StartScreen,
+    Playing,
+    GameOver,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    One,
+    Two,
+}
+
+/// Who is at the controls. In single player mode the computer drives Player 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    SinglePlayer,
+    TwoPlayer,
+}
+
+/// Everything a player can ask the game to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// Start a new match from the start or game over screen.
+    Start,
+    /// Pick the number of players on the start screen.
+    SelectMode(Mode),
+    /// Step through the computer difficulty levels on the start screen.
+    CycleDifficulty,
+    /// Which way a player holds their paddle from now on.
+    Hold(Player, Hold),
+    /// Freeze or unfreeze a match in progress.
+    TogglePause,
+}
+
+/// Which way a player is holding their paddle. The paddle keeps moving every tick until the
+/// hold changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Hold {
+    #[default]
+    Still,
+    Up,
+    Down,
+}
+
+impl Hold {
+    /// The hold for a pair of keys, standing still when both or neither are down.
+    pub fn from_keys(up: bool, down: bool) -> Self {
+        match (up, down) {
+            (true, false) => Hold::Up,
+            (false, true) => Hold::Down,
+            _ => Hold::Still,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Paddle {
+    pub x: Fixed,
+    pub y: Fixed,
+    /// Distance travelled during the last tick, passed on to the ball as spin.
+    pub vel_y: Fixed,
+    pub hold: Hold,
+    last_y: Fixed,
+}
+
+impl Paddle {
+    fn new(x: Fixed, y: Fixed) -> Self {
+        Self { x, y, vel_y: Fixed::ZERO, hold: Hold::Still, last_y: y }
+    }
+
+    pub fn centre_y(&self) -> Fixed {
+        self.y + Fixed::from(PADDLE_HEIGHT / 2)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        Aabb::new(self.x, self.y, Fixed::from(PADDLE_WIDTH), Fixed::from(PADDLE_HEIGHT))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ball {
+    /// Top left corner.
+    pub pos: Vec2,
+    pub vel: Vec2,
+    /// Length of `vel`, kept separately so that it does not drift through rounding.
+    pub speed: Fixed,
+}
+
+impl Ball {
+    pub fn centre_y(&self) -> Fixed {
+        self.pos.y + Fixed::from(BALL_SIZE / 2)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        Aabb { pos: self.pos, size: Vec2::new(Fixed::from(BALL_SIZE), Fixed::from(BALL_SIZE)) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Surface {
+    Wall,
+    Paddle(Player),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PongGame {
+    pub field_width: usize,
+    pub field_height: usize,
+
+    pub player1: Paddle,
+    pub player2: Paddle,
+    pub ball: Ball,
+
+    pub player1_score: usize,
+    pub player2_score: usize,
+    /// Paddle hits since the last serve.
+    pub rally: u32,
+
+    pub state: GameState,
+    /// While set, `step` leaves a match in progress untouched.
+    pub paused: bool,
+    pub mode: Mode,
+    pub difficulty: Difficulty,
+
+    /// Seed for serve angles and the computer opponent, so that a match can be replayed.
+    pub seed: u32,
+    rng: XorShift32,
+    ai: Option<AiController>,
+}
+
+impl PongGame {
+    /// Creates a game for a playfield of the given size, waiting on the start screen.
+    pub fn new(field_width: usize, field_height: usize) -> Self {
+        let paddle_y = Fixed::from(field_height / 2) - Fixed::from(PADDLE_HEIGHT / 2);
+        let player2_x = Fixed::from(field_width) - Fixed::from(PADDLE_WIDTH + PADDLE_MARGIN);
+        Self {
+            field_width,
+            field_height,
+            player1: Paddle::new(Fixed::from(PADDLE_MARGIN), paddle_y),
+            player2: Paddle::new(player2_x, paddle_y),
+            ball: Ball {
+                pos: Self::centre(field_width, field_height),
+                vel: Vec2::ZERO,
+                speed: Fixed::ZERO,
+            },
+            player1_score: 0,
+            player2_score: 0,
+            rally: 0,
+            state: GameState::StartScreen,
+            paused: false,
+            mode: Mode::TwoPlayer,
+            difficulty: Difficulty::Normal,
+            seed: 0,
+            rng: XorShift32::new(0),
+            ai: None,
+        }
+    }
+
+    /// Puts the paddles, ball and scores back to their starting values and begins play.
+    pub fn restart(&mut self) {
+        let ai = match self.mode {
+            Mode::SinglePlayer => Some(AiController::new(Player::Two, self.difficulty, self.seed)),
+            Mode::TwoPlayer => None,
+        };
+        *self = Self {
+            state: GameState::Playing,
+            mode: self.mode,
+            difficulty: self.difficulty,
+            seed: self.seed,
+            rng: XorShift32::new(self.seed),
+            ai,
+            ..Self::new(self.field_width, self.field_height)
+        };
+        self.serve(Player::Two);
+    }
+
+    /// The player who reached `WINNING_SCORE`, if the match is over.
+    pub fn winner(&self) -> Option<Player> {
+        if self.player1_score >= WINNING_SCORE {
+            Some(Player::One)
+        } else if self.player2_score >= WINNING_SCORE {
+            Some(Player::Two)
+        } else {
+            None
+        }
+    }
+
+    /// The paddle currently driven by the computer, if any.
+    pub fn computer_player(&self) -> Option<Player> {
+        self.ai.as_ref().map(AiController::player)
+    }
+
+    pub fn paddle(&self, player: Player) -> &Paddle {
+        match player {
+            Player::One => &self.player1,
+            Player::Two => &self.player2,
+        }
+    }
+
+    fn paddle_mut(&mut self, player: Player) -> &mut Paddle {
+        match player {
+            Player::One => &mut self.player1,
+            Player::Two => &mut self.player2,
+        }
+    }
+
+    /// Moves a paddle by `dy` field units, keeping it on the field.
+    pub fn move_paddle(&mut self, player: Player, dy: Fixed) {
+        let max_y = Fixed::from(self.field_height.saturating_sub(PADDLE_HEIGHT));
+        let paddle = self.paddle_mut(player);
+        paddle.y = (paddle.y + dy).clamp(Fixed::ZERO, max_y);
+    }
+
+    /// Reacts to a single player input.
+    pub fn apply_input(&mut self, input: Input) {
+        match (self.state, input) {
+            (GameState::StartScreen | GameState::GameOver, Input::Start) => self.restart(),
+            (GameState::StartScreen, Input::SelectMode(mode)) => self.mode = mode,
+            (GameState::StartScreen, Input::CycleDifficulty) => self.difficulty = self.difficulty.next(),
+            (GameState::Playing, Input::Hold(player, _)) if self.computer_player() == Some(player) => {}
+            (GameState::Playing, Input::Hold(player, hold)) => self.paddle_mut(player).hold = hold,
+            (GameState::Playing, Input::TogglePause) => self.paused = !self.paused,
+            _ => {}
+        }
+    }
+
+    /// Advances the game by one timer tick.
+    pub fn step(&mut self) {
+        match self.state {
+            GameState::StartScreen => {}
+            GameState::Playing if self.paused => {}
+            GameState::Playing => {
+                if let Some(mut ai) = self.ai.take() {
+                    ai.drive(self);
+                    self.ai = Some(ai);
+                }
+                for player in [Player::One, Player::Two] {
+                    match self.paddle(player).hold {
+                        Hold::Still => {}
+                        Hold::Up => self.move_paddle(player, -Fixed::from(PADDLE_SPEED)),
+                        Hold::Down => self.move_paddle(player, Fixed::from(PADDLE_SPEED)),
+                    }
+                }
+                for paddle in [&mut self.player1, &mut self.player2] {
+                    paddle.vel_y = paddle.y - paddle.last_y;
+                    paddle.last_y = paddle.y;
+                }
+                self.step_ball();
+            }
+            GameState::GameOver => {
+                self.ball.vel = Vec2::ZERO;
+            }
+        }
+    }
+
+    fn centre(field_width: usize, field_height: usize) -> Vec2 {
+        Vec2::new(Fixed::from(field_width / 2), Fixed::from(field_height / 2))
+    }
+
+    fn step_ball(&mut self) {
+        // Sweep the ball along its path, bouncing off whatever it meets first, until the
+        // tick is used up.
+        let mut remaining = Fixed::ONE;
+        for _ in 0..MAX_BOUNCES_PER_TICK {
+            let delta = self.ball.vel * remaining;
+            let bounds = self.ball.bounds();
+            let first = self
+                .surfaces()
+                .into_iter()
+                .filter_map(|(surface, target)| sweep(&bounds, delta, &target).map(|hit| (surface, hit)))
+                .min_by_key(|(_, hit)| hit.time);
+
+            let Some((surface, hit)) = first else {
+                self.ball.pos += delta;
+                break;
+            };
+            self.ball.pos += delta * hit.time;
+            remaining -= remaining * hit.time;
+
+            match surface {
+                Surface::Paddle(player) if hit.normal.x != Fixed::ZERO => self.return_ball(player),
+                // Walls, and the top or bottom end of a paddle
+                _ => self.ball.vel.y = -self.ball.vel.y,
+            }
+        }
+
+        let ball_size = Fixed::from(BALL_SIZE);
+        if self.ball.pos.x + ball_size <= Fixed::ZERO {
+            self.score(Player::Two);
+        } else if self.ball.pos.x >= Fixed::from(self.field_width) {
+            self.score(Player::One);
+        }
+    }
+
+    /// Everything the ball can bounce off: a wall just outside the top and bottom of the
+    /// screen, and both paddles.
+    fn surfaces(&self) -> [(Surface, Aabb); 4] {
+        let width = Fixed::from(self.field_width);
+        let height = Fixed::from(self.field_height);
+        [
+            (Surface::Wall, Aabb::new(-width, -height, width * 3, height)),
+            (Surface::Wall, Aabb::new(-width, height, width * 3, height)),
+            (Surface::Paddle(Player::One), self.player1.bounds()),
+            (Surface::Paddle(Player::Two), self.player2.bounds()),
+        ]
+    }
+
+    /// Sends the ball back from `player`'s paddle. The further from the centre of the paddle the
+    /// ball hits, the steeper it leaves; a moving paddle adds spin, and every return is faster.
+    fn return_ball(&mut self, player: Player) {
+        let paddle = *self.paddle(player);
+        let reach = Fixed::from((PADDLE_HEIGHT + BALL_SIZE) / 2);
+        let offset = ((self.ball.centre_y() - paddle.centre_y()) / reach).clamp(-Fixed::ONE, Fixed::ONE);
+
+        self.rally += 1;
+        self.ball.speed = (self.ball.speed + RALLY_SPEED_UP).min(MAX_BALL_SPEED);
+        self.ball.vel = launch(
+            self.ball.speed,
+            offset * MAX_BOUNCE_SIN,
+            paddle.vel_y * SPIN_FACTOR,
+            direction_from(player),
+        );
+    }
+
+    /// Puts the ball back in the middle and sends it towards `receiver` at a random angle.
+    fn serve(&mut self, receiver: Player) {
+        let sin = Fixed::from_ratio(self.rng.symmetric(1000), 1000) * MAX_SERVE_SIN;
+        let towards = match receiver {
+            Player::One => direction_from(Player::Two),
+            Player::Two => direction_from(Player::One),
+        };
+        self.rally = 0;
+        self.ball = Ball {
+            pos: Self::centre(self.field_width, self.field_height),
+            vel: launch(SERVE_SPEED, sin, Fixed::ZERO, towards),
+            speed: SERVE_SPEED,
+        };
+    }
+
+    fn score(&mut self, player: Player) {
+        match player {
+            Player::One => self.player1_score += 1,
+            Player::Two => self.player2_score += 1,
+        }
+        if self.winner().is_some() {
+            self.state = GameState::GameOver;
+        } else {
+            self.serve(player);
+        }
+    }
+}
+
+/// Horizontal direction of a ball leaving `player`'s side: 1 for rightwards, -1 for leftwards.
+fn direction_from(player: Player) -> i32 {
+    match player {
+        Player::One => 1,
+        Player::Two => -1,
+    }
+}
+
+/// A velocity of length `speed` heading in `direction`, whose vertical part is `sin * speed`
+/// plus `spin`, but never steeper than the steepest bounce.
+fn launch(speed: Fixed, sin: Fixed, spin: Fixed, direction: i32) -> Vec2 {
+    let limit = speed * MAX_BOUNCE_SIN;
+    let vel_y = (speed * sin + spin).clamp(-limit, limit);
+    let vel_x = (speed * speed - vel_y * vel_y).sqrt();
+    Vec2::new(vel_x * direction, vel_y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 1280;
+    const HEIGHT: usize = 800;
+
+    fn px(value: i32) -> Fixed {
+        Fixed::from_int(value)
+    }
+
+    fn playing() -> PongGame {
+        let mut game = PongGame::new(WIDTH, HEIGHT);
+        game.apply_input(Input::Start);
+        game
+    }
+
+    /// A game with the ball about to reach Player 1's paddle at `offset` units below its centre.
+    fn incoming_at_player1(offset: i32) -> PongGame {
+        let mut game = playing();
+        game.ball.pos = Vec2::new(
+            game.player1.x + px(PADDLE_WIDTH as i32 + 5),
+            game.player1.centre_y() + px(offset - BALL_SIZE as i32 / 2),
+        );
+        game.ball.vel = Vec2::new(-SERVE_SPEED, Fixed::ZERO);
+        game.ball.speed = SERVE_SPEED;
+        game
+    }
+
+    #[test]
+    fn new_game_waits_on_start_screen() {
+        let mut game = PongGame::new(WIDTH, HEIGHT);
+        assert_eq!(game.state, GameState::StartScreen);
+
+        let before = game.clone();
+        game.apply_input(Input::Hold(Player::One, Hold::Up));
+        game.step();
+        assert_eq!(game, before);
+    }
+
+    #[test]
+    fn hold_follows_the_pair_of_keys() {
+        assert_eq!(Hold::from_keys(true, false), Hold::Up);
+        assert_eq!(Hold::from_keys(false, true), Hold::Down);
+        assert_eq!(Hold::from_keys(true, true), Hold::Still);
+        assert_eq!(Hold::from_keys(false, false), Hold::Still);
+    }
+
+    #[test]
+    fn start_input_begins_play() {
+        let game = playing();
+        assert_eq!(game.state, GameState::Playing);
+        assert_eq!(game.ball.pos, Vec2::new(px(WIDTH as i32 / 2), px(HEIGHT as i32 / 2)));
+        assert_eq!((game.player1_score, game.player2_score), (0, 0));
+        assert!(game.ball.vel.x > Fixed::ZERO);
+    }
+
+    #[test]
+    fn serves_vary_in_angle_and_keep_their_speed() {
+        let mut game = playing();
+        let mut angles = [Fixed::ZERO; 8];
+        for angle in angles.iter_mut() {
+            game.serve(Player::One);
+            assert!(game.ball.vel.x.is_negative());
+            assert!((game.ball.vel.length() - SERVE_SPEED).abs() < Fixed::from_ratio(1, 100));
+            assert!(game.ball.vel.y.abs() <= SERVE_SPEED * MAX_SERVE_SIN);
+            *angle = game.ball.vel.y;
+        }
+        assert!(angles.iter().any(|&angle| angle != angles[0]));
+    }
+
+    #[test]
+    fn paddles_move_and_stop_at_edges() {
+        let mut game = playing();
+        let start = game.player1.y;
+        game.apply_input(Input::Hold(Player::One, Hold::Up));
+        game.apply_input(Input::Hold(Player::Two, Hold::Down));
+        game.step();
+        assert_eq!(game.player1.y, start - px(PADDLE_SPEED as i32));
+        assert_eq!(game.player2.y, start + px(PADDLE_SPEED as i32));
+
+        // Both keep moving for as long as the keys are held...
+        for _ in 0..100 {
+            game.step();
+        }
+        assert_eq!(game.player1.y, Fixed::ZERO);
+        assert_eq!(game.player2.y, px((HEIGHT - PADDLE_HEIGHT) as i32));
+
+        // ...and stop when they are released.
+        game.apply_input(Input::Hold(Player::Two, Hold::Up));
+        game.apply_input(Input::Hold(Player::Two, Hold::Still));
+        game.step();
+        assert_eq!(game.player2.y, px((HEIGHT - PADDLE_HEIGHT) as i32));
+    }
+
+    #[test]
+    fn ball_bounces_off_top_and_bottom_walls() {
+        let mut game = playing();
+        game.ball.pos.y = px(6);
+        game.ball.vel.y = px(-8);
+        game.step();
+        assert_eq!(game.ball.pos.y, px(2));
+        assert_eq!(game.ball.vel.y, px(8));
+
+        game.ball.pos.y = px((HEIGHT - BALL_SIZE) as i32 - 6);
+        game.step();
+        assert_eq!(game.ball.pos.y, px((HEIGHT - BALL_SIZE) as i32 - 2));
+        assert_eq!(game.ball.vel.y, px(-8));
+    }
+
+    #[test]
+    fn centre_hit_returns_the_ball_straight() {
+        let mut game = incoming_at_player1(0);
+        game.step();
+        assert_eq!(game.ball.vel.y, Fixed::ZERO);
+        assert_eq!(game.ball.vel.x, SERVE_SPEED + RALLY_SPEED_UP);
+        assert_eq!(game.rally, 1);
+    }
+
+    #[test]
+    fn bounce_angle_follows_the_hit_offset() {
+        let mut low = incoming_at_player1(20);
+        low.step();
+        let mut lower = incoming_at_player1(50);
+        lower.step();
+        let mut high = incoming_at_player1(-50);
+        high.step();
+
+        assert!(low.ball.vel.y > Fixed::ZERO);
+        assert!(lower.ball.vel.y > low.ball.vel.y);
+        assert!((high.ball.vel.y + lower.ball.vel.y).abs() < Fixed::from_ratio(1, 100));
+        for game in [&low, &lower, &high] {
+            assert!(game.ball.vel.x > Fixed::ZERO);
+            assert!((game.ball.vel.length() - game.ball.speed).abs() < Fixed::from_ratio(1, 100));
+        }
+    }
+
+    #[test]
+    fn moving_paddle_puts_spin_on_the_ball() {
+        let mut game = incoming_at_player1(0);
+        game.apply_input(Input::Hold(Player::One, Hold::Down));
+        // Keep the ball level with the paddle after it moved.
+        game.ball.pos.y += px(PADDLE_SPEED as i32);
+        game.step();
+
+        assert_eq!(game.player1.vel_y, px(PADDLE_SPEED as i32));
+        assert!(game.ball.vel.y > Fixed::ZERO);
+    }
+
+    #[test]
+    fn long_rallies_speed_up_to_a_limit() {
+        let mut game = playing();
+        for _ in 0..100 {
+            game.ball.pos.x = game.player1.x + px(PADDLE_WIDTH as i32 + 5);
+            game.ball.pos.y = game.player1.y;
+            game.ball.vel.x = -game.ball.vel.x.abs();
+            game.step();
+        }
+        assert_eq!(game.rally, 100);
+        assert_eq!(game.ball.speed, MAX_BALL_SPEED);
+    }
+
+    #[test]
+    fn ball_bounces_off_paddles() {
+        let mut game = incoming_at_player1(0);
+        game.step();
+        assert!(game.ball.vel.x > Fixed::ZERO);
+
+        game.ball.pos.x = game.player2.x - px(BALL_SIZE as i32 + 5);
+        game.ball.pos.y = game.player2.centre_y();
+        game.step();
+        assert!(game.ball.vel.x.is_negative());
+        assert_eq!(game.rally, 2);
+    }
+
+    #[test]
+    fn fast_ball_cannot_tunnel_through_a_paddle() {
+        let mut game = incoming_at_player1(0);
+        // Far more than the paddle is wide: a check after the move would miss it.
+        game.ball.pos.x = game.player1.x + px(150);
+        game.ball.vel.x = px(-200);
+        game.step();
+
+        assert!(game.ball.vel.x > Fixed::ZERO);
+        assert_eq!((game.player1_score, game.player2_score), (0, 0));
+        // The ball is sent back from the face of the paddle with what was left of the tick.
+        assert!(game.ball.pos.x > game.player1.x + px(PADDLE_WIDTH as i32));
+    }
+
+    #[test]
+    fn ball_passing_beside_a_paddle_is_not_returned() {
+        let mut game = playing();
+        game.player1.y = Fixed::ZERO;
+        // Just below the paddle, where the old 15px buffer used to catch it.
+        game.ball.pos = Vec2::new(game.player1.x + px(20), px(PADDLE_HEIGHT as i32 + 5));
+        game.ball.vel = Vec2::new(-SERVE_SPEED, Fixed::ZERO);
+        game.step();
+        assert!(game.ball.vel.x.is_negative());
+    }
+
+    #[test]
+    fn ball_hitting_the_end_of_a_paddle_bounces_vertically() {
+        let mut game = playing();
+        game.ball.pos = Vec2::new(game.player1.x, game.player1.y - px(BALL_SIZE as i32 + 4));
+        game.ball.vel = Vec2::new(Fixed::ZERO, px(8));
+        game.step();
+        // Half the tick down to the paddle, the other half back up.
+        assert_eq!(game.ball.vel.y, px(-8));
+        assert_eq!(game.ball.pos.y, game.player1.y - px(BALL_SIZE as i32 + 4));
+    }
+
+    #[test]
+    fn tiny_screens_do_not_underflow() {
+        for (width, height) in [(0, 0), (10, 10), (40, 60)] {
+            let mut game = PongGame::new(width, height);
+            game.apply_input(Input::Start);
+            game.apply_input(Input::Hold(Player::One, Hold::Down));
+            for _ in 0..100 {
+                game.step();
+            }
+        }
+    }
+
+    #[test]
+    fn missed_ball_scores_for_the_other_player() {
+        let mut game = playing();
+        game.player1.y = Fixed::ZERO;
+        game.ball.pos = Vec2::new(px(0), px(HEIGHT as i32 / 2));
+        game.ball.vel = Vec2::new(-SERVE_SPEED, Fixed::ZERO);
+        game.step();
+        assert_eq!((game.player1_score, game.player2_score), (0, 1));
+        assert_eq!(game.ball.pos, Vec2::new(px(WIDTH as i32 / 2), px(HEIGHT as i32 / 2)));
+        assert!(game.ball.vel.x > Fixed::ZERO);
+        assert_eq!((game.rally, game.ball.speed), (0, SERVE_SPEED));
+
+        game.player2.y = Fixed::ZERO;
+        game.ball.pos = Vec2::new(px(WIDTH as i32 - 5), px(HEIGHT as i32 / 2));
+        game.step();
+        assert_eq!((game.player1_score, game.player2_score), (1, 1));
+        assert_eq!(game.state, GameState::Playing);
+    }
+
+    #[test]
+    fn reaching_winning_score_ends_the_game() {
+        let mut game = playing();
+        game.player1_score = WINNING_SCORE - 1;
+        game.player2.y = Fixed::ZERO;
+        game.ball.pos = Vec2::new(px(WIDTH as i32 - 5), px(HEIGHT as i32 / 2));
+        game.step();
+
+        assert_eq!(game.state, GameState::GameOver);
+        assert_eq!(game.winner(), Some(Player::One));
+
+        game.step();
+        assert_eq!(game.ball.vel, Vec2::ZERO);
+    }
+
+    #[test]
+    fn start_screen_selects_mode_and_difficulty() {
+        let mut game = PongGame::new(WIDTH, HEIGHT);
+        game.apply_input(Input::SelectMode(Mode::SinglePlayer));
+        game.apply_input(Input::CycleDifficulty);
+        assert_eq!((game.mode, game.difficulty), (Mode::SinglePlayer, Difficulty::Hard));
+
+        game.apply_input(Input::Start);
+        assert_eq!(game.computer_player(), Some(Player::Two));
+
+        // Settings are fixed for the length of a match.
+        game.apply_input(Input::SelectMode(Mode::TwoPlayer));
+        assert_eq!(game.mode, Mode::SinglePlayer);
+    }
+
+    #[test]
+    fn computer_paddle_ignores_keyboard() {
+        let mut game = PongGame::new(WIDTH, HEIGHT);
+        game.apply_input(Input::SelectMode(Mode::SinglePlayer));
+        game.apply_input(Input::Start);
+
+        game.apply_input(Input::Hold(Player::Two, Hold::Up));
+        assert_eq!(game.player2.hold, Hold::Still);
+    }
+
+    #[test]
+    fn pause_freezes_the_match() {
+        let mut game = playing();
+        game.apply_input(Input::Hold(Player::One, Hold::Down));
+        game.apply_input(Input::TogglePause);
+        let frozen = game.clone();
+        for _ in 0..10 {
+            game.step();
+        }
+        assert_eq!(game, frozen);
+
+        game.apply_input(Input::TogglePause);
+        game.step();
+        assert_ne!(game.ball.pos, frozen.ball.pos);
+        assert!(game.player1.y > frozen.player1.y);
+    }
+
+    #[test]
+    fn pause_only_applies_to_a_match_in_progress() {
+        let mut game = PongGame::new(WIDTH, HEIGHT);
+        game.apply_input(Input::TogglePause);
+        assert!(!game.paused);
+
+        let mut game = playing();
+        game.apply_input(Input::TogglePause);
+        game.state = GameState::GameOver;
+        game.apply_input(Input::Start);
+        assert!(!game.paused);
+    }
+
+    #[test]
+    fn move_paddle_stays_on_screen() {
+        let mut game = playing();
+        game.move_paddle(Player::One, px(-10_000));
+        assert_eq!(game.player1.y, Fixed::ZERO);
+        game.move_paddle(Player::One, px(10_000));
+        assert_eq!(game.player1.y, px((HEIGHT - PADDLE_HEIGHT) as i32));
+    }
+
+    #[test]
+    fn start_input_restarts_after_game_over() {
+        let mut game = playing();
+        game.player2_score = WINNING_SCORE;
+        game.state = GameState::GameOver;
+        game.apply_input(Input::Start);
+
+        assert_eq!(game.state, GameState::Playing);
+        assert_eq!(game.winner(), None);
+        assert_eq!((game.player1_score, game.player2_score), (0, 0));
+    }
+}
@@ -0,0 +1,15 @@
+// Game logic shared by the kernel and host-side `cargo test`. Nothing in here may depend on
+// `bootloader_api`, `x86_64` or any other hardware-facing crate.
+#![cfg_attr(not(test), no_std)]
+
+pub mod ai;
+pub mod collision;
+pub mod fixed;
+pub mod game;
+mod rng;
+pub mod viewport;
+
+pub use ai::{AiController, Difficulty};
+pub use fixed::{Fixed, Vec2};
+pub use game::{Ball, GameState, Hold, Input, Mode, Paddle, Player, PongGame, FIELD_HEIGHT, FIELD_WIDTH, WINNING_SCORE};
+pub use viewport::{ScreenRect, Viewport};
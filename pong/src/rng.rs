@@ -0,0 +1,23 @@
+/// Small deterministic generator so that games can be replayed from a seed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XorShift32(u32);
+
+impl XorShift32 {
+    pub fn new(seed: u32) -> Self {
+        Self(if seed == 0 { 0x9E37_79B9 } else { seed })
+    }
+
+    pub fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// Uniform value in `-bound..=bound`.
+    pub fn symmetric(&mut self, bound: u32) -> i32 {
+        (self.next() % (2 * bound + 1)) as i32 - bound as i32
+    }
+}
@@ -0,0 +1,147 @@
+// Mapping from the virtual playfield to screen pixels. The field is scaled uniformly to the
+// largest size that fits and centred, leaving black bars on the sides or top and bottom.
+
+use crate::fixed::{Fixed, Vec2};
+
+/// A rectangle of screen pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    /// Where the field's top left corner lands on screen.
+    pub x: usize,
+    pub y: usize,
+    /// Size of the field on screen.
+    pub width: usize,
+    pub height: usize,
+    /// Screen pixels per field unit, as the exact ratio `num / den`.
+    num: usize,
+    den: usize,
+}
+
+impl Viewport {
+    /// Fits a `field_width` by `field_height` field into a screen of the given size.
+    pub fn fit(field_width: usize, field_height: usize, screen_width: usize, screen_height: usize) -> Self {
+        let (num, den) = if field_width == 0 || field_height == 0 {
+            (0, 1)
+        } else if screen_width * field_height > screen_height * field_width {
+            // Wider than the field: full height, bars left and right.
+            (screen_height, field_height)
+        } else {
+            (screen_width, field_width)
+        };
+        let width = field_width * num / den;
+        let height = field_height * num / den;
+        Self {
+            x: (screen_width - width) / 2,
+            y: (screen_height - height) / 2,
+            width,
+            height,
+            num,
+            den,
+        }
+    }
+
+    /// Screen pixels per field unit.
+    pub fn scale(&self) -> Fixed {
+        Fixed::from_ratio(self.num as i32, self.den as i32)
+    }
+
+    /// Screen position of a point on the field. Points off the field map outside the viewport.
+    pub fn point(&self, point: Vec2) -> (isize, isize) {
+        (self.x as isize + self.length(point.x), self.y as isize + self.length(point.y))
+    }
+
+    /// The visible part of a box on the field, or `None` if it lies entirely off the field.
+    /// Both edges are mapped separately, so boxes that touch on the field touch on screen too.
+    pub fn rect(&self, pos: Vec2, size: Vec2) -> Option<ScreenRect> {
+        let clamp = |value: isize, max: usize| value.clamp(0, max as isize) as usize;
+        let left = clamp(self.length(pos.x), self.width);
+        let right = clamp(self.length(pos.x + size.x), self.width);
+        let top = clamp(self.length(pos.y), self.height);
+        let bottom = clamp(self.length(pos.y + size.y), self.height);
+        (left < right && top < bottom).then_some(ScreenRect {
+            x: self.x + left,
+            y: self.y + top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+
+    /// A distance on the field in screen pixels, rounded to the nearest.
+    pub fn length(&self, units: Fixed) -> isize {
+        let scaled = units.raw() as i64 * self.num as i64;
+        let unit = (self.den as i64) << Fixed::FRAC_BITS;
+        (scaled + unit / 2).div_euclid(unit) as isize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{FIELD_HEIGHT, FIELD_WIDTH};
+
+    fn units(value: i32) -> Fixed {
+        Fixed::from_int(value)
+    }
+
+    fn field_on(screen_width: usize, screen_height: usize) -> Viewport {
+        Viewport::fit(FIELD_WIDTH, FIELD_HEIGHT, screen_width, screen_height)
+    }
+
+    #[test]
+    fn wide_screens_get_bars_left_and_right() {
+        let viewport = field_on(1920, 1080);
+        assert_eq!((viewport.x, viewport.y, viewport.width, viewport.height), (240, 0, 1440, 1080));
+    }
+
+    #[test]
+    fn tall_screens_get_bars_top_and_bottom() {
+        let viewport = field_on(1000, 1000);
+        assert_eq!((viewport.x, viewport.y, viewport.width, viewport.height), (0, 125, 1000, 750));
+    }
+
+    #[test]
+    fn matching_aspect_ratio_fills_the_screen() {
+        let viewport = field_on(640, 480);
+        assert_eq!((viewport.x, viewport.y), (0, 0));
+        assert_eq!(viewport.point(Vec2::new(units(1000), units(750))), (640, 480));
+        assert_eq!(viewport.scale(), Fixed::from_ratio(64, 100));
+    }
+
+    #[test]
+    fn geometry_keeps_its_proportions_at_every_resolution() {
+        let paddle = (Vec2::new(units(30), units(325)), Vec2::new(units(15), units(100)));
+        for (width, height) in [(640, 480), (800, 600), (1280, 960), (2000, 1500)] {
+            let viewport = field_on(width, height);
+            let rect = viewport.rect(paddle.0, paddle.1).unwrap();
+            assert_eq!(rect.height * 750, 100 * height);
+            assert_eq!(rect.y * 1000, 325 * width);
+        }
+    }
+
+    #[test]
+    fn boxes_are_clipped_to_the_field() {
+        let viewport = field_on(1920, 1080);
+        let ball = Vec2::new(units(12), units(12));
+
+        let half_out = viewport.rect(Vec2::new(units(-6), units(100)), ball).unwrap();
+        assert_eq!(half_out.x, viewport.x);
+        assert!(half_out.width < viewport.length(units(12)) as usize);
+
+        assert_eq!(viewport.rect(Vec2::new(units(-20), units(100)), ball), None);
+        assert_eq!(viewport.rect(Vec2::new(units(1000), units(100)), ball), None);
+    }
+
+    #[test]
+    fn empty_field_or_screen_does_not_panic() {
+        assert_eq!(Viewport::fit(0, 0, 640, 480).width, 0);
+        assert_eq!(Viewport::fit(FIELD_WIDTH, FIELD_HEIGHT, 0, 0).length(units(500)), 0);
+    }
+}
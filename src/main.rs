@@ -1,6 +1,21 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::Stdio;
 use ovmf_prebuilt::{Arch, FileType, Prebuilt, Source};
+use symbolize::Symbolizer;
+
+mod symbolize;
+
+/// TCP port for the kernel's GDB stub when `--gdb` is given without one.
+const DEFAULT_GDB_PORT: u16 = 1234;
 
 fn main() {
+    // `--gdb[=PORT]` connects COM2, where the kernel's GDB stub listens, to a local TCP port.
+    let gdb_port = std::env::args().skip(1).find_map(|arg| match arg.as_str() {
+        "--gdb" => Some(DEFAULT_GDB_PORT),
+        _ => arg.strip_prefix("--gdb=").map(|port| port.parse().expect("--gdb=PORT needs a port number")),
+    });
+
     // read env variables that were set in build script
     let uefi_path = env!("UEFI_PATH");
     println!("Using image: {}", uefi_path);
@@ -19,8 +34,46 @@ fn main() {
     // set kernel image
     cmd.arg("-drive").arg(format!("format=raw,file={uefi_path}"));
     cmd.arg("-serial").arg("stdio");
+    if let Some(port) = gdb_port {
+        // The second serial port is COM2. `nowait` boots without waiting for GDB to connect.
+        cmd.arg("-serial").arg(format!("tcp:127.0.0.1:{port},server,nowait"));
+        println!("GDB stub on COM2: connect with `target remote localhost:{port}`");
+    }
     
-    // launch qemu and wait until it terminates
-    let mut child = cmd.spawn().unwrap();
+    // launch qemu, pass its serial output through with crash reports symbolized, and wait
+    // until it terminates
+    let mut child = cmd.stdout(Stdio::piped()).spawn().unwrap();
+    let serial = child.stdout.take().unwrap();
+    let symbolizer = Symbolizer::new(Path::new(env!("KERNEL_PATH")));
+    if let Err(error) = pass_through(serial, symbolizer) {
+        eprintln!("Reading the serial output failed: {error}");
+    }
     child.wait().unwrap();
+}
+
+/// Copies `serial` to stdout as it arrives, so prompts without a newline show up at once, and
+/// prints the symbolizer's notes after each complete line.
+fn pass_through(mut serial: impl Read, mut symbolizer: Symbolizer) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    let mut buffer = [0; 4096];
+    let mut line = Vec::new();
+    loop {
+        let len = serial.read(&mut buffer)?;
+        if len == 0 {
+            return Ok(());
+        }
+        stdout.write_all(&buffer[..len])?;
+        for &byte in &buffer[..len] {
+            if byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line);
+            for note in symbolizer.annotate(text.trim_end_matches('\r')) {
+                writeln!(stdout, "{note}")?;
+            }
+            line.clear();
+        }
+        stdout.flush()?;
+    }
 }
\ No newline at end of file
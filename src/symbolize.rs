@@ -0,0 +1,227 @@
+// Turns the kernel's crash reports on the serial port into source locations. The kernel logs
+// where it was loaded at boot and again before every backtrace (see `kernel/src/crash.rs`), so
+// the addresses in a report, minus that offset, can be looked up in the kernel ELF's symbols and
+// DWARF line tables.
+
+use std::error::Error;
+use std::ops::Range;
+use std::path::Path;
+use addr2line::Loader;
+use object::{Object, ObjectSegment};
+
+const OFFSET_MARKER: &str = "kernel image offset ";
+const PANIC_MARKER: &str = "PANIC:";
+const FRAME_MARKER: &str = "BACKTRACE: #";
+const END_MARKER: &str = "BACKTRACE: end";
+/// Follows the address of a frame an exception interrupted, which is not a return address.
+const INTERRUPTED_MARKER: &str = "interrupted";
+
+pub struct Symbolizer {
+    /// None if the kernel ELF could not be read; lines then pass through untouched.
+    kernel: Option<Kernel>,
+    kernel_image_offset: Option<u64>,
+    /// Between a `PANIC:` line and the end of its backtrace.
+    in_report: bool,
+}
+
+impl Symbolizer {
+    pub fn new(kernel: &Path) -> Self {
+        let kernel = match Kernel::read(kernel) {
+            Ok(kernel) => Some(kernel),
+            Err(error) => {
+                eprintln!("Crash reports will not be symbolized, reading {} failed: {error}", kernel.display());
+                None
+            }
+        };
+        Self { kernel, kernel_image_offset: None, in_report: false }
+    }
+
+    /// Looks at one line of serial output and returns the lines to print after it: the source
+    /// locations of the addresses in it, if it is part of a crash report.
+    pub fn annotate(&mut self, line: &str) -> Vec<String> {
+        if let Some(offset) = find_offset(line) {
+            self.kernel_image_offset = Some(offset);
+        }
+        if line.contains(PANIC_MARKER) {
+            self.in_report = true;
+        }
+        if !self.in_report {
+            return Vec::new();
+        }
+        if line.contains(END_MARKER) {
+            self.in_report = false;
+            return Vec::new();
+        }
+
+        if let Some(frame) = line.split_once(FRAME_MARKER).map(|(_, frame)| frame) {
+            let Some(address) = hex_numbers(frame).next() else { return Vec::new() };
+            if frame.contains(INTERRUPTED_MARKER) {
+                return self.locate(address);
+            }
+            // A return address is the instruction after the call; the call is the byte before.
+            return self.locate(address.saturating_sub(1));
+        }
+        // Register dumps and messages: every number that lands in the kernel's code.
+        hex_numbers(line).flat_map(|address| self.locate(address)).collect()
+    }
+
+    /// The functions, innermost inlined one first, and source lines of the code at the
+    /// run-time address `address`. Empty if it is not in the kernel.
+    fn locate(&self, address: u64) -> Vec<String> {
+        let (Some(kernel), Some(offset)) = (&self.kernel, self.kernel_image_offset) else { return Vec::new() };
+        let Some(address) = address.checked_sub(offset) else { return Vec::new() };
+        // The symbol table gives the closest symbol below any address, even far past the end.
+        if !kernel.segments.iter().any(|segment| segment.contains(&address)) {
+            return Vec::new();
+        }
+        let loader = &kernel.loader;
+        let Some(symbol) = loader.find_symbol(address) else { return Vec::new() };
+
+        let mut lines = Vec::new();
+        if let Ok(mut frames) = loader.find_frames(address) {
+            while let Ok(Some(frame)) = frames.next() {
+                let function = frame
+                    .function
+                    .and_then(|function| function.demangle().ok().map(|name| name.into_owned()))
+                    .unwrap_or_else(|| demangle(symbol));
+                let location = frame.location.map_or_else(
+                    || "??".to_string(),
+                    |location| format!("{}:{}", shorten(location.file.unwrap_or("??")), location.line.unwrap_or(0)),
+                );
+                lines.push(format!("        at {function} ({location})"));
+            }
+        }
+        if lines.is_empty() {
+            lines.push(format!("        at {}", demangle(symbol)));
+        }
+        lines
+    }
+}
+
+/// The kernel ELF's debug info and the addresses its loadable segments take up.
+struct Kernel {
+    loader: Loader,
+    segments: Vec<Range<u64>>,
+}
+
+impl Kernel {
+    fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let loader = Loader::new(path)?;
+        let data = std::fs::read(path)?;
+        let segments = object::File::parse(&*data)?
+            .segments()
+            .map(|segment| segment.address()..segment.address() + segment.size())
+            .collect();
+        Ok(Self { loader, segments })
+    }
+}
+
+/// The offset in lines like `Kernel image offset 0xffff800000000000`.
+fn find_offset(line: &str) -> Option<u64> {
+    let lowercase = line.to_ascii_lowercase();
+    let at = lowercase.find(OFFSET_MARKER)? + OFFSET_MARKER.len();
+    hex_numbers(&line[at..]).next()
+}
+
+/// Every `0x` number in `text`.
+fn hex_numbers(text: &str) -> impl Iterator<Item = u64> + '_ {
+    text.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter_map(|word| word.strip_prefix("0x"))
+        .filter_map(|digits| u64::from_str_radix(digits, 16).ok())
+}
+
+fn demangle(symbol: &str) -> String {
+    addr2line::demangle_auto(symbol.into(), None).into_owned()
+}
+
+/// Source paths in this workspace relative to it; others, like the standard library's, as they are.
+fn shorten(path: &str) -> &str {
+    path.strip_prefix(env!("CARGO_MANIFEST_DIR")).map_or(path, |relative| relative.trim_start_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFSET: u64 = 0xffff_8000_0000_0000;
+
+    /// A symbolizer for the kernel this runner was built with, and the run-time address of its
+    /// entry point as if it was loaded at [OFFSET].
+    fn kernel() -> (Symbolizer, u64) {
+        let path = Path::new(env!("KERNEL_PATH"));
+        let elf = std::fs::read(path).unwrap();
+        let entry = u64::from_le_bytes(elf[24..32].try_into().unwrap());
+        (Symbolizer::new(path), OFFSET + entry)
+    }
+
+    fn report(symbolizer: &mut Symbolizer) {
+        assert!(symbolizer.annotate(&format!("BACKTRACE: kernel image offset {OFFSET:#x}")).is_empty());
+        assert!(symbolizer.annotate("PANIC: panicked at kernel/src/main.rs:1:1:").is_empty());
+    }
+
+    #[test]
+    fn finds_the_image_offset_in_boot_and_report_lines() {
+        assert_eq!(find_offset("[    0.001000] INFO  kernel: Kernel image offset 0xffff800000000000"), Some(OFFSET));
+        assert_eq!(find_offset("BACKTRACE: kernel image offset 0x8000"), Some(0x8000));
+        assert_eq!(find_offset("BACKTRACE: #0 0xffff800000001234"), None);
+    }
+
+    #[test]
+    fn reads_every_hex_number_and_nothing_else() {
+        let numbers: Vec<_> = hex_numbers("rip    0x0000000000812a4b  rsp 0x10, 42 0xzz (0xff)").collect();
+        assert_eq!(numbers, [0x812a4b, 0x10, 0xff]);
+    }
+
+    #[test]
+    fn backtrace_addresses_in_the_kernel_get_their_source() {
+        let (mut symbolizer, entry) = kernel();
+        report(&mut symbolizer);
+        // Return addresses are looked up one byte back, at the call.
+        let notes = symbolizer.annotate(&format!("BACKTRACE: #0 {:#x}", entry + 1));
+        assert!(!notes.is_empty());
+        assert!(notes.iter().all(|note| note.starts_with("        at ")), "{notes:?}");
+        assert!(notes.iter().any(|note| note.contains(".rs:")), "{notes:?}");
+    }
+
+    #[test]
+    fn interrupted_frames_are_looked_up_where_they_are() {
+        let (mut symbolizer, entry) = kernel();
+        report(&mut symbolizer);
+        // Taken as it is, not as a return address, so it lands where the one a byte later does.
+        let interrupted = symbolizer.annotate(&format!("BACKTRACE: #2 {entry:#x} interrupted"));
+        let returned_to = symbolizer.annotate(&format!("BACKTRACE: #2 {:#x}", entry + 1));
+        assert!(!interrupted.is_empty());
+        assert_eq!(interrupted, returned_to);
+    }
+
+    #[test]
+    fn addresses_outside_the_kernel_are_left_alone() {
+        let (mut symbolizer, _) = kernel();
+        report(&mut symbolizer);
+        assert!(symbolizer.annotate("BACKTRACE: #0 0x1000").is_empty());
+        assert!(symbolizer.annotate(&format!("BACKTRACE: #1 {:#x}", OFFSET + 0x10_0000_0000)).is_empty());
+        assert!(symbolizer.annotate("rsp    0x0000010000203f48").is_empty());
+    }
+
+    #[test]
+    fn lines_outside_a_report_are_left_alone() {
+        let (mut symbolizer, entry) = kernel();
+        symbolizer.annotate(&format!("Kernel image offset {OFFSET:#x}"));
+        assert!(symbolizer.annotate(&format!("jumping to {entry:#x}")).is_empty());
+
+        report(&mut symbolizer);
+        assert!(!symbolizer.annotate(&format!("rip {entry:#x}")).is_empty());
+        assert!(symbolizer.annotate("BACKTRACE: end").is_empty());
+        assert!(symbolizer.annotate(&format!("rip {entry:#x}")).is_empty());
+    }
+
+    #[test]
+    fn addresses_before_the_offset_is_known_are_left_alone() {
+        let (mut symbolizer, entry) = kernel();
+        assert!(symbolizer.annotate("PANIC: panicked at kernel/src/main.rs:1:1:").is_empty());
+        assert!(symbolizer.annotate(&format!("BACKTRACE: #0 {:#x}", entry + 1)).is_empty());
+
+        assert!(symbolizer.annotate(&format!("BACKTRACE: kernel image offset {OFFSET:#x}")).is_empty());
+        assert!(!symbolizer.annotate(&format!("BACKTRACE: #0 {:#x}", entry + 1)).is_empty());
+    }
+}